@@ -0,0 +1,120 @@
+//! Runs a small Plumtree cluster over loopback UDP sockets.
+//!
+//! Each node is identified by the string representation of its socket address.
+//! Protocol messages are encoded as JSON via the `serde` feature of this crate.
+//!
+//! ```console
+//! $ cargo run --example udp_cluster --features serde
+//! ```
+use plumtree::message::{Message, ProtocolMessage};
+use plumtree::{Action, Node, System};
+use std::io;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+const NODE_COUNT: usize = 5;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+struct UdpSystem;
+impl System for UdpSystem {
+    type NodeId = String;
+    type MessageId = u64;
+    type MessagePayload = String;
+}
+
+struct UdpNode {
+    node: Node<UdpSystem>,
+    socket: UdpSocket,
+    last_tick: Instant,
+    delivered: usize,
+}
+impl UdpNode {
+    fn new() -> io::Result<Self> {
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        socket.set_nonblocking(true)?;
+        let id = socket.local_addr()?.to_string();
+        Ok(UdpNode {
+            node: Node::new(id),
+            socket,
+            last_tick: Instant::now(),
+            delivered: 0,
+        })
+    }
+
+    fn run_once(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        self.node.clock_mut().tick(now - self.last_tick);
+        self.last_tick = now;
+
+        let mut buf = [0; 65536];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((size, _)) => {
+                    match serde_json::from_slice::<ProtocolMessage<UdpSystem>>(&buf[..size]) {
+                        Ok(message) => {
+                            self.node.handle_protocol_message(message);
+                        }
+                        Err(e) => eprintln!("[{}] Malformed datagram: {}", self.node.id(), e),
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        while let Some(action) = self.node.poll_action() {
+            match action {
+                Action::Send {
+                    destination,
+                    message,
+                } => {
+                    let bytes = serde_json::to_vec(&message)?;
+                    self.socket.send_to(&bytes, destination.as_str())?;
+                }
+                Action::Deliver { message } => {
+                    println!(
+                        "[{}] Delivered: id={}, payload={:?}",
+                        self.node.id(),
+                        message.id,
+                        message.payload
+                    );
+                    self.delivered += 1;
+                }
+                Action::ForgetSuggested { .. } => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut nodes = (0..NODE_COUNT)
+        .map(|_| UdpNode::new())
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // Ring topology
+    for i in 0..NODE_COUNT {
+        let j = (i + 1) % NODE_COUNT;
+        let (a, b) = (nodes[i].node.id().clone(), nodes[j].node.id().clone());
+        nodes[i].node.handle_neighbor_up(&b);
+        nodes[j].node.handle_neighbor_up(&a);
+    }
+
+    for (i, n) in nodes.iter_mut().enumerate() {
+        let payload = format!("hello from {}", n.node.id());
+        n.node.broadcast_message(Message::new(i as u64, payload));
+    }
+
+    let started = Instant::now();
+    while nodes.iter().any(|n| n.delivered < NODE_COUNT) {
+        if started.elapsed() > TIMEOUT {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Broadcast timeout"));
+        }
+        for n in &mut nodes {
+            n.run_once()?;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    println!("All messages have been delivered to all nodes");
+    Ok(())
+}
@@ -1,7 +1,9 @@
 use crate::message::{Message, ProtocolMessage};
+use crate::time::{Clock, NodeTime};
 use crate::System;
-use std::collections::VecDeque;
+use std::collections::{vec_deque, HashMap, VecDeque};
 use std::fmt;
+use std::time::Duration;
 
 /// Actions instructed by Plumtree [Node].
 ///
@@ -26,6 +28,17 @@ pub enum Action<T: System> {
         /// The message to be delivered.
         message: Message<T>,
     },
+
+    /// Suggest that the message can be forgotten (see [`Node::forget_message`]).
+    ///
+    /// This is issued only if [`NodeOptions::suggest_forget`] is `true`.
+    ///
+    /// [`Node::forget_message`]: ./struct.Node.html#method.forget_message
+    /// [`NodeOptions::suggest_forget`]: ./struct.NodeOptions.html#structfield.suggest_forget
+    ForgetSuggested {
+        /// The identifier of the message.
+        message_id: T::MessageId,
+    },
 }
 impl<T: System> Action<T> {
     pub(crate) fn send<M>(destination: T::NodeId, message: M) -> Self
@@ -55,26 +68,192 @@ where
                 destination, message
             ),
             Action::Deliver { message } => write!(f, "Deliver {{ message: {:?} }}", message),
+            Action::ForgetSuggested { message_id } => {
+                write!(f, "ForgetSuggested {{ message_id: {:?} }}", message_id)
+            }
         }
     }
 }
 
-pub struct ActionQueue<T: System>(VecDeque<Action<T>>);
+/// An iterator that drains the actions queued in a [Node].
+///
+/// This is created by [`Node::drain_actions`].
+///
+/// [Node]: ./struct.Node.html
+/// [`Node::drain_actions`]: ./struct.Node.html#method.drain_actions
+pub struct DrainActions<'a, T: System>(&'a mut ActionQueue<T>);
+impl<'a, T: System> DrainActions<'a, T> {
+    pub(crate) fn new(queue: &'a mut ActionQueue<T>) -> Self {
+        DrainActions(queue)
+    }
+}
+impl<'a, T: System> Iterator for DrainActions<'a, T> {
+    type Item = Action<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.pop()
+    }
+}
+impl<'a, T: System> fmt::Debug for DrainActions<'a, T>
+where
+    T::NodeId: fmt::Debug,
+    T::MessageId: fmt::Debug,
+    T::MessagePayload: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DrainActions({:?})", self.0)
+    }
+}
+
+type SendObserver<T> = Box<dyn FnMut(&<T as System>::NodeId, &ProtocolMessage<T>) + Send>;
+
+pub struct ActionQueue<T: System> {
+    queue: VecDeque<Action<T>>,
+    send_observer: Option<SendObserver<T>>,
+    delivered_count: u64,
+    rate_limit: Option<(usize, Duration)>,
+    now: NodeTime,
+    windows: HashMap<T::NodeId, SendWindow<T>>,
+}
 impl<T: System> ActionQueue<T> {
     pub fn new() -> Self {
-        ActionQueue(VecDeque::new())
+        ActionQueue {
+            queue: VecDeque::new(),
+            send_observer: None,
+            delivered_count: 0,
+            rate_limit: None,
+            now: Clock::new().now(),
+            windows: HashMap::new(),
+        }
+    }
+
+    pub fn advance(&mut self, now: NodeTime, rate_limit: Option<(usize, Duration)>) {
+        self.now = now;
+        self.rate_limit = rate_limit;
+        let (limit, window) = match rate_limit {
+            None => {
+                self.release_all();
+                return;
+            }
+            Some(x) => x,
+        };
+
+        let queue = &mut self.queue;
+        self.windows.retain(|destination, w| {
+            if w.start.checked_add(window).is_some_and(|t| t <= now) {
+                w.start = now;
+                w.count = 0;
+            }
+            while w.count < limit {
+                if let Some(message) = w.throttled.pop_front() {
+                    queue.push_back(Action::send(destination.clone(), message));
+                    w.count += 1;
+                } else {
+                    break;
+                }
+            }
+            w.count > 0 || !w.throttled.is_empty()
+        });
+    }
+
+    pub fn release_all(&mut self) {
+        for (destination, w) in self.windows.drain() {
+            for message in w.throttled {
+                self.queue
+                    .push_back(Action::send(destination.clone(), message));
+            }
+        }
+    }
+
+    pub fn throttled_len(&self) -> usize {
+        self.windows.values().map(|w| w.throttled.len()).sum()
+    }
+
+    pub fn next_release_time(&self) -> Option<NodeTime> {
+        let window = self.rate_limit?.1;
+        self.windows
+            .values()
+            .filter(|w| !w.throttled.is_empty())
+            .filter_map(|w| w.start.checked_add(window))
+            .min()
+    }
+
+    pub fn set_send_observer(&mut self, observer: SendObserver<T>) {
+        self.send_observer = Some(observer);
     }
 
     pub fn send<M: Into<ProtocolMessage<T>>>(&mut self, destination: T::NodeId, message: M) {
-        self.0.push_back(Action::send(destination, message));
+        let message = message.into();
+        if let Some(observer) = self.send_observer.as_mut() {
+            observer(&destination, &message);
+        }
+        if let Some((limit, window)) = self.rate_limit {
+            let now = self.now;
+            let w = self
+                .windows
+                .entry(destination.clone())
+                .or_insert_with(|| SendWindow::new(now));
+            if w.start.checked_add(window).is_some_and(|t| t <= now) {
+                w.start = now;
+                w.count = 0;
+            }
+            if w.count >= limit || !w.throttled.is_empty() {
+                w.throttled.push_back(message);
+                return;
+            }
+            w.count += 1;
+        }
+        self.queue.push_back(Action::send(destination, message));
     }
 
     pub fn deliver(&mut self, message: Message<T>) {
-        self.0.push_back(Action::Deliver { message });
+        self.delivered_count += 1;
+        self.queue.push_back(Action::Deliver { message });
+    }
+
+    pub fn suggest_forget(&mut self, message_id: T::MessageId) {
+        self.queue.push_back(Action::ForgetSuggested { message_id });
+    }
+
+    pub fn delivered_count(&self) -> u64 {
+        self.delivered_count
     }
 
     pub fn pop(&mut self) -> Option<Action<T>> {
-        self.0.pop_front()
+        self.queue.pop_front()
+    }
+
+    pub fn remove_sends_to(&mut self, peer: &T::NodeId) -> usize {
+        let before = self.queue.len();
+        self.queue.retain(|action| match action {
+            Action::Send { destination, .. } => destination != peer,
+            _ => true,
+        });
+        let throttled = self.windows.remove(peer).map_or(0, |w| w.throttled.len());
+        before - self.queue.len() + throttled
+    }
+
+    pub fn take_sends_to(&mut self, peer: &T::NodeId) -> Vec<Action<T>> {
+        let mut taken = Vec::new();
+        let mut rest = VecDeque::with_capacity(self.queue.len());
+        for action in self.queue.drain(..) {
+            match action {
+                Action::Send {
+                    ref destination, ..
+                } if destination == peer => taken.push(action),
+                _ => rest.push_back(action),
+            }
+        }
+        self.queue = rest;
+        taken
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn iter(&self) -> vec_deque::Iter<'_, Action<T>> {
+        self.queue.iter()
     }
 }
 impl<T: System> fmt::Debug for ActionQueue<T>
@@ -84,6 +263,40 @@ where
     T::MessagePayload: fmt::Debug,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "ActionQueue({:?})", self.0)
+        write!(
+            f,
+            "ActionQueue {{ queue: {:?}, delivered_count: {:?}, rate_limit: {:?}, now: {:?}, \
+             windows: {:?} }}",
+            self.queue, self.delivered_count, self.rate_limit, self.now, self.windows
+        )
+    }
+}
+
+struct SendWindow<T: System> {
+    start: NodeTime,
+    count: usize,
+    throttled: VecDeque<ProtocolMessage<T>>,
+}
+impl<T: System> SendWindow<T> {
+    fn new(start: NodeTime) -> Self {
+        SendWindow {
+            start,
+            count: 0,
+            throttled: VecDeque::new(),
+        }
+    }
+}
+impl<T: System> fmt::Debug for SendWindow<T>
+where
+    T::NodeId: fmt::Debug,
+    T::MessageId: fmt::Debug,
+    T::MessagePayload: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SendWindow {{ start: {:?}, count: {:?}, throttled: {:?} }}",
+            self.start, self.count, self.throttled
+        )
     }
 }
@@ -0,0 +1,39 @@
+//! Utilities for analyzing the state of multiple Plumtree nodes.
+//!
+//! This module is available only if the `analysis` feature is enabled.
+use crate::{Node, System};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Returns the depth of the spanning tree formed by the eager push edges of `nodes`,
+/// traversing from `root` in breadth-first order.
+///
+/// If `root` is not contained in `nodes` or some of `nodes` are unreachable from `root`,
+/// this function will return `None`.
+///
+/// Peers which are not contained in `nodes` are ignored.
+pub fn eager_tree_depth<T: System>(nodes: &[Node<T>], root: &T::NodeId) -> Option<usize> {
+    let nodes = nodes.iter().map(|n| (n.id(), n)).collect::<HashMap<_, _>>();
+    if !nodes.contains_key(root) {
+        return None;
+    }
+
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::new();
+    let mut max_depth = 0;
+    visited.insert(root);
+    queue.push_back((root, 0));
+    while let Some((id, depth)) = queue.pop_front() {
+        max_depth = depth;
+        for peer in nodes[id].eager_push_peers() {
+            if nodes.contains_key(peer) && visited.insert(peer) {
+                queue.push_back((peer, depth + 1));
+            }
+        }
+    }
+
+    if visited.len() == nodes.len() {
+        Some(max_depth)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,85 @@
+use crate::message::IhaveMessage;
+use crate::time::{Clock, NodeTime};
+use crate::System;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+pub struct DelayedIhaves<T: System> {
+    queue: VecDeque<(NodeTime, T::NodeId, IhaveMessage<T>)>,
+    pendings: HashMap<(T::NodeId, T::MessageId), NodeTime>,
+}
+impl<T: System> fmt::Debug for DelayedIhaves<T>
+where
+    T::NodeId: fmt::Debug,
+    T::MessageId: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "DelayedIhaves {{ queue: {:?}, pendings: {:?} }}",
+            self.queue, self.pendings
+        )
+    }
+}
+impl<T: System> DelayedIhaves<T> {
+    pub fn new() -> Self {
+        DelayedIhaves {
+            queue: VecDeque::new(),
+            pendings: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, destination: T::NodeId, ihave: IhaveMessage<T>, send_time: NodeTime) {
+        let key = (destination.clone(), ihave.message_id.clone());
+        self.pendings.insert(key, send_time);
+        self.queue.push_back((send_time, destination, ihave));
+    }
+
+    pub fn suppress(&mut self, destination: &T::NodeId, message_id: &T::MessageId) {
+        if !self.pendings.is_empty() {
+            self.pendings
+                .remove(&(destination.clone(), message_id.clone()));
+        }
+    }
+
+    pub fn cancel(&mut self, message_id: &T::MessageId) {
+        self.pendings.retain(|(_, id), _| id != message_id);
+    }
+
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T::MessageId) -> bool,
+    {
+        self.pendings.retain(|(_, id), _| f(id));
+    }
+
+    pub fn pop_due(&mut self, clock: &Clock) -> Option<(T::NodeId, IhaveMessage<T>)> {
+        while self.queue.front().is_some_and(|x| x.0 <= clock.now()) {
+            let (send_time, destination, ihave) = self.queue.pop_front().expect("never fails");
+            let key = (destination, ihave.message_id.clone());
+            if self.pendings.get(&key) == Some(&send_time) {
+                self.pendings.remove(&key);
+                return Some((key.0, ihave));
+            }
+        }
+        None
+    }
+
+    pub fn pendings_for<'a>(
+        &'a self,
+        destination: &'a T::NodeId,
+    ) -> impl Iterator<Item = (&'a T::MessageId, u16)> + 'a {
+        self.queue
+            .iter()
+            .filter(move |x| x.1 == *destination)
+            .filter(move |x| {
+                let key = (x.1.clone(), x.2.message_id.clone());
+                self.pendings.get(&key) == Some(&x.0)
+            })
+            .map(|x| (&x.2.message_id, x.2.round))
+    }
+
+    pub fn next_send_time(&self) -> Option<NodeTime> {
+        self.queue.front().map(|x| x.0)
+    }
+}
@@ -0,0 +1,270 @@
+use crate::message::IhaveMessage;
+use crate::time::NodeTime;
+use crate::System;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::fmt;
+
+pub struct OutstandingGrafts<T: System> {
+    entries: HashMap<T::MessageId, GraftEntry<T>>,
+}
+impl<T: System> fmt::Debug for OutstandingGrafts<T>
+where
+    T::NodeId: fmt::Debug,
+    T::MessageId: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OutstandingGrafts {{ entries: {:?} }}", self.entries)
+    }
+}
+impl<T: System> OutstandingGrafts<T> {
+    pub fn new() -> Self {
+        OutstandingGrafts {
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, message_id: T::MessageId, peer: T::NodeId, deadline: NodeTime) {
+        self.entries
+            .entry(message_id)
+            .or_insert_with(GraftEntry::new)
+            .outstanding
+            .push((peer, deadline));
+    }
+
+    pub fn is_full<F>(&self, message_id: &T::MessageId, now: NodeTime, limit: usize, f: F) -> bool
+    where
+        F: Fn(&T::NodeId) -> bool,
+    {
+        self.entries
+            .get(message_id)
+            .is_some_and(|e| e.outstandings(now, &f) >= limit)
+    }
+
+    pub fn defer(&mut self, ihave: IhaveMessage<T>) {
+        self.entries
+            .entry(ihave.message_id.clone())
+            .or_insert_with(GraftEntry::new)
+            .deferred
+            .push_back(ihave);
+    }
+
+    pub fn pop_ready<F>(&mut self, now: NodeTime, limit: usize, f: F) -> Option<IhaveMessage<T>>
+    where
+        F: Fn(&T::NodeId) -> bool,
+    {
+        self.entries
+            .values_mut()
+            .find(|e| !e.deferred.is_empty() && e.outstandings(now, &f) < limit)
+            .and_then(|e| e.deferred.pop_front())
+    }
+
+    pub fn pop_deferred(&mut self, message_id: &T::MessageId) -> Option<IhaveMessage<T>> {
+        self.entries
+            .get_mut(message_id)
+            .and_then(|e| e.deferred.pop_front())
+    }
+
+    pub fn remove_outstanding(&mut self, message_id: &T::MessageId, peer: &T::NodeId) {
+        if let Some(e) = self.entries.get_mut(message_id) {
+            e.outstanding.retain(|x| x.0 != *peer);
+        }
+    }
+
+    pub fn remove(&mut self, message_id: &T::MessageId) {
+        self.entries.remove(message_id);
+    }
+
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T::MessageId) -> bool,
+    {
+        self.entries.retain(|id, _| f(id));
+    }
+
+    pub fn sweep(&mut self, now: NodeTime) {
+        self.entries.retain(|_, e| {
+            e.outstanding.retain(|x| x.1 > now);
+            !(e.outstanding.is_empty() && e.deferred.is_empty())
+        });
+    }
+
+    pub fn next_deadline(&self) -> Option<NodeTime> {
+        self.entries
+            .values()
+            .filter(|e| !e.deferred.is_empty())
+            .flat_map(|e| e.outstanding.iter().map(|x| x.1))
+            .min()
+    }
+}
+
+struct GraftEntry<T: System> {
+    outstanding: Vec<(T::NodeId, NodeTime)>,
+    deferred: VecDeque<IhaveMessage<T>>,
+}
+impl<T: System> GraftEntry<T> {
+    fn new() -> Self {
+        GraftEntry {
+            outstanding: Vec::new(),
+            deferred: VecDeque::new(),
+        }
+    }
+
+    fn outstandings<F>(&self, now: NodeTime, f: F) -> usize
+    where
+        F: Fn(&T::NodeId) -> bool,
+    {
+        self.outstanding
+            .iter()
+            .filter(|x| x.1 > now && f(&x.0))
+            .count()
+    }
+}
+impl<T: System> fmt::Debug for GraftEntry<T>
+where
+    T::NodeId: fmt::Debug,
+    T::MessageId: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "GraftEntry {{ outstanding: {:?}, deferred: {:?} }}",
+            self.outstanding, self.deferred
+        )
+    }
+}
+
+type Deadlines<T> = Vec<(<T as System>::NodeId, NodeTime, u64)>;
+
+pub struct GraftDeadlines<T: System> {
+    entries: HashMap<T::MessageId, Deadlines<T>>,
+    queue: BinaryHeap<DeadlineItem<T>>,
+    seqno: u64,
+}
+impl<T: System> fmt::Debug for GraftDeadlines<T>
+where
+    T::NodeId: fmt::Debug,
+    T::MessageId: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "GraftDeadlines {{ entries: {:?}, seqno: {:?}, .. }}",
+            self.entries, self.seqno
+        )
+    }
+}
+impl<T: System> GraftDeadlines<T> {
+    pub fn new() -> Self {
+        GraftDeadlines {
+            entries: HashMap::new(),
+            queue: BinaryHeap::new(),
+            seqno: 0,
+        }
+    }
+
+    pub fn insert(
+        &mut self,
+        message_id: T::MessageId,
+        peer: T::NodeId,
+        deadline: NodeTime,
+    ) -> bool {
+        let seqno = self.seqno;
+        self.seqno += 1;
+        let entries = self.entries.entry(message_id.clone()).or_default();
+        let before = entries.len();
+        entries.retain(|x| x.0 != peer);
+        let replaced = entries.len() != before;
+        entries.push((peer, deadline, seqno));
+        self.queue.push(DeadlineItem {
+            deadline,
+            seqno,
+            message_id,
+        });
+        replaced
+    }
+
+    pub fn resolve(
+        &mut self,
+        message_id: &T::MessageId,
+        sender: &T::NodeId,
+        now: NodeTime,
+    ) -> (u64, u64) {
+        let mut entries = match self.entries.remove(message_id) {
+            None => return (0, 0),
+            Some(entries) => entries,
+        };
+        // At most one entry (preferably the one for the sender) is satisfied
+        let i = entries
+            .iter()
+            .position(|x| x.0 == *sender)
+            .unwrap_or(entries.len() - 1);
+        let (_, deadline, _) = entries.swap_remove(i);
+        let satisfied = u64::from(now < deadline);
+        (satisfied, entries.len() as u64 + 1 - satisfied)
+    }
+
+    pub fn remove(&mut self, message_id: &T::MessageId, peer: &T::NodeId) -> bool {
+        let entries = match self.entries.get_mut(message_id) {
+            None => return false,
+            Some(entries) => entries,
+        };
+        let before = entries.len();
+        entries.retain(|x| x.0 != *peer);
+        let removed = entries.len() != before;
+        if entries.is_empty() {
+            self.entries.remove(message_id);
+        }
+        removed
+    }
+
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T::MessageId) -> bool,
+    {
+        self.entries.retain(|id, _| f(id));
+    }
+
+    pub fn pop_expired(&mut self, now: NodeTime) -> u64 {
+        let mut expired = 0;
+        while self.queue.peek().is_some_and(|x| x.deadline <= now) {
+            let item = self.queue.pop().expect("never fails");
+            if let Some(entries) = self.entries.get_mut(&item.message_id) {
+                let before = entries.len();
+                entries.retain(|x| x.2 != item.seqno);
+                expired += (before - entries.len()) as u64;
+                if entries.is_empty() {
+                    self.entries.remove(&item.message_id);
+                }
+            }
+        }
+        expired
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.queue.clear();
+    }
+}
+
+struct DeadlineItem<T: System> {
+    deadline: NodeTime,
+    seqno: u64,
+    message_id: T::MessageId,
+}
+impl<T: System> PartialEq for DeadlineItem<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl<T: System> Eq for DeadlineItem<T> {}
+impl<T: System> PartialOrd for DeadlineItem<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<T: System> Ord for DeadlineItem<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.deadline, other.seqno).cmp(&(self.deadline, self.seqno))
+    }
+}
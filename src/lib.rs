@@ -6,24 +6,48 @@
 //!
 //! [Plumtree]: http://www.gsd.inesc-id.pt/~ler/reports/srds07.pdf
 #![warn(missing_docs)]
-pub use action::Action;
-pub use node::{Node, NodeOptions};
-pub use system::System;
+pub use action::{Action, DrainActions};
+pub use node::{
+    DeliveryOrder, DropEvent, DropReason, GraftStats, HandleError, MessageProvenance,
+    NewNeighborMode, Node, NodeEvent, NodeOptions, OptimizationThreshold, PeerChange, ResyncOrder,
+};
+pub use policy::{DefaultTreePolicy, TreePolicy};
+pub use state::NodeState;
+pub use system::{BasicSystem, System};
+pub use transform::{IdentityTransform, PayloadTransform};
+pub use verify::Verifier;
 
 mod action;
+mod delayed;
+mod graft;
 mod missing;
 mod node;
+mod policy;
+mod recent;
+mod state;
 mod system;
+mod transform;
+mod verify;
 
+#[cfg(feature = "analysis")]
+pub mod analysis;
 pub mod message;
+pub mod rng;
 pub mod time;
 
 #[cfg(test)]
 mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
     use std::time::Duration;
 
     use super::*;
-    use message::Message;
+    use message::{
+        GossipMessage, GraftMessage, GraftNackMessage, IhaveManyMessage, IhaveMessage, Message,
+        ProbeAckMessage, ProbeMessage, ProtocolMessage, PruneMessage,
+    };
+    use rng::{RngSource, SplitMix64};
 
     struct TestSystem;
     impl System for TestSystem {
@@ -90,36 +114,3092 @@ mod tests {
 
     #[test]
     fn many_node_works() {
-        let mut nodes: Vec<Node<TestSystem>> = (0..500).map(|i| Node::new(i.to_string())).collect();
+        let (nodes, _) = simulate(&mut SplitMix64::new(0), 500, NodeOptions::default());
+        for node in &nodes {
+            assert_eq!(node.messages().len(), MESSAGE_COUNT);
+            assert_eq!(node.waiting_messages(), 0);
+        }
+    }
+
+    #[test]
+    fn simulation_is_reproducible() {
+        let options = NodeOptions {
+            shuffle_eager_order: true,
+            ..NodeOptions::default()
+        };
+        for seed in 0..3 {
+            let (mut nodes0, trace0) = simulate(&mut SplitMix64::new(seed), 100, options.clone());
+            let (mut nodes1, trace1) = simulate(&mut SplitMix64::new(seed), 100, options.clone());
+            assert_eq!(trace0, trace1);
+
+            // The node-side generators are seeded from the harness generator as well
+            let states0 = nodes0
+                .iter_mut()
+                .map(|n| n.rng_mut().next_u64())
+                .collect::<Vec<_>>();
+            let states1 = nodes1
+                .iter_mut()
+                .map(|n| n.rng_mut().next_u64())
+                .collect::<Vec<_>>();
+            assert_eq!(states0, states1);
+            assert_eq!(states0.iter().collect::<HashSet<_>>().len(), states0.len());
+        }
+    }
+
+    const MESSAGE_COUNT: usize = 50;
+
+    fn simulate(
+        rng: &mut SplitMix64,
+        node_count: usize,
+        options: NodeOptions,
+    ) -> (Vec<Node<TestSystem>>, Trace) {
+        let mut nodes: Vec<Node<TestSystem>> = (0..node_count)
+            .map(|i| {
+                let mut node = Node::with_options(i.to_string(), options.clone());
+                node.set_rng(SplitMix64::new(rng.next_u64()));
+                node
+            })
+            .collect();
+
+        // setup neighbors
+        for i in 0..nodes.len() {
+            let neighbors = rng.next_u64() as usize % 3 + 1;
+            for _ in 0..neighbors {
+                let j = rng.next_u64() as usize % nodes.len();
+                nodes[i].handle_neighbor_up(&j.to_string());
+                nodes[j].handle_neighbor_up(&i.to_string());
+            }
+        }
+
+        // broadcast messages
+        for m in 0..MESSAGE_COUNT {
+            let sender = rng.next_u64() as usize % nodes.len();
+            nodes[sender].broadcast_message(message(m as u64));
+        }
+
+        let trace = execute(&mut nodes);
+        (nodes, trace)
+    }
+
+    #[test]
+    fn manual_tree_control_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        let peer = "bar".to_owned();
+        assert!(!node.promote_to_eager(&peer));
+        assert!(!node.demote_to_lazy(&peer));
+
+        node.handle_neighbor_up(&peer);
+        assert!(node.eager_push_peers().contains(&peer));
+        assert!(!node.promote_to_eager(&peer));
+
+        assert!(node.demote_to_lazy(&peer));
+        assert!(node.lazy_push_peers().contains(&peer));
+        assert!(!node.eager_push_peers().contains(&peer));
+        assert!(matches!(
+            node.poll_action(),
+            Some(Action::Send { destination, message: ProtocolMessage::Prune(_) }) if destination == peer
+        ));
+        assert!(!node.demote_to_lazy(&peer));
+
+        assert!(node.promote_to_eager(&peer));
+        assert!(node.eager_push_peers().contains(&peer));
+        assert!(!node.lazy_push_peers().contains(&peer));
+        assert!(matches!(
+            node.poll_action(),
+            Some(Action::Send { destination, message: ProtocolMessage::Graft(m) })
+                if destination == peer && m.message_id.is_none()
+        ));
+        assert!(node.poll_action().is_none());
+    }
+
+    #[test]
+    fn peer_scores_determine_pruned_link() {
+        for &(bar_score, baz_score, pruned) in &[(0, 0, "baz"), (1, 10, "bar"), (10, 1, "baz")] {
+            let mut node = Node::<TestSystem>::new("foo".to_owned());
+            node.handle_neighbor_up(&"bar".to_owned());
+            node.handle_neighbor_up(&"baz".to_owned());
+            node.set_peer_score(&"bar".to_owned(), bar_score);
+            node.set_peer_score(&"baz".to_owned(), baz_score);
+
+            node.handle_protocol_message(gossip("bar", 0, 0));
+            node.handle_protocol_message(gossip("baz", 0, 0));
+            let prunes = drain_sends(&mut node)
+                .into_iter()
+                .filter(|(_, m)| matches!(m, ProtocolMessage::Prune(_)))
+                .map(|(destination, _)| destination)
+                .collect::<Vec<_>>();
+            assert_eq!(prunes, vec![pruned.to_owned()]);
+            assert_eq!(node.eager_push_peers().len(), 1);
+            assert!(node.lazy_push_peers().contains(pruned));
+        }
+    }
+
+    #[test]
+    fn timeout_queue_len_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        for peer in &["bar", "baz", "qux"] {
+            node.handle_neighbor_up(&peer.to_string());
+            node.handle_protocol_message(ihave(peer, 0, 0, false));
+        }
+        assert_eq!(node.waiting_messages(), 1);
+        assert_eq!(node.timeout_queue_len(), 3);
+    }
+
+    #[test]
+    fn realtime_ihave_timeout_works() {
+        let options = NodeOptions {
+            realtime_ihave_timeout: Duration::from_millis(100),
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_protocol_message(ihave("bar", 0, 0, true));
+        assert!(node.poll_action().is_none());
+
+        node.clock_mut().tick(Duration::from_millis(99));
+        assert!(node.poll_action().is_none());
+
+        node.clock_mut().tick(Duration::from_millis(1));
+        assert!(matches!(
+            node.poll_action(),
+            Some(Action::Send { message: ProtocolMessage::Graft(m), .. }) if m.message_id == Some(0)
+        ));
+    }
+
+    #[test]
+    fn handle_neighbor_up_many_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.broadcast_message(message(0));
+        node.broadcast_message(message(1));
+        execute_single(&mut node);
+
+        node.handle_neighbor_up_many(
+            ["bar", "baz", "qux", "bar", "foo"]
+                .iter()
+                .map(|s| s.to_string()),
+        );
+        assert_eq!(node.eager_push_peers().len(), 3);
+
+        let sends = drain_sends(&mut node);
+        assert_eq!(sends.len(), 6);
+        assert!(sends
+            .iter()
+            .all(|(_, m)| matches!(m, ProtocolMessage::Ihave(m) if !m.realtime)));
+    }
+
+    #[test]
+    fn send_observer_works() {
+        let observed = Arc::new(AtomicUsize::new(0));
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        let counter = Arc::clone(&observed);
+        node.set_send_observer(move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.demote_to_lazy(&"baz".to_owned());
+
+        node.broadcast_message(message(0));
+        let sends = drain_sends(&mut node);
+        assert_eq!(sends.len(), 3);
+        assert_eq!(observed.load(Ordering::SeqCst), sends.len());
+    }
+
+    #[test]
+    fn forget_messages_before_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        for id in 0..10 {
+            node.broadcast_message(message(id));
+        }
+        node.handle_protocol_message(ihave("bar", 3, 0, false));
+        node.handle_protocol_message(ihave("bar", 10, 0, false));
+        node.handle_protocol_message(ihave("bar", 20, 0, false));
+        assert_eq!(node.waiting_messages(), 2);
+
+        assert_eq!(node.forget_messages_before(&5), 5);
+        assert_eq!(node.messages().len(), 5);
+        assert!((5..10).all(|id| node.messages().contains_key(&id)));
+        assert_eq!(node.waiting_messages(), 2);
+
+        assert_eq!(node.forget_messages_before(&15), 5);
+        assert_eq!(node.waiting_messages(), 1);
+    }
+
+    #[test]
+    fn forget_messages_before_behaves_like_forget_message() {
+        let window = Duration::from_millis(100);
+        let options = NodeOptions {
+            forgotten_message_memory: 10,
+            lazy_suppression_window: window,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned()]);
+        node.demote_to_lazy(&"baz".to_owned());
+        node.drain_actions().count();
+
+        node.handle_protocol_message(gossip("bar", 0, 0));
+        node.drain_actions().count();
+        assert_eq!(node.forget_messages_before(&1), 1);
+
+        // No delayed IHAVE is sent for the forgotten message
+        node.clock_mut().tick(window);
+        assert_eq!(drain_sends(&mut node).len(), 0);
+
+        // A late duplicate is not delivered again
+        node.handle_protocol_message(gossip("bar", 0, 0));
+        assert!(!node
+            .drain_actions()
+            .any(|a| matches!(a, Action::Deliver { .. })));
+    }
+
+    #[test]
+    fn peek_actions_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        assert_eq!(node.peek_actions().count(), 0);
+
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.broadcast_message(message(0));
+        let peeked = node
+            .peek_actions()
+            .map(|a| format!("{:?}", a))
+            .collect::<Vec<_>>();
+        assert_eq!(peeked.len(), 2);
+
+        let mut polled = Vec::new();
+        while let Some(action) = node.poll_action() {
+            polled.push(format!("{:?}", action));
+        }
+        assert_eq!(peeked, polled);
+        assert_eq!(node.peek_actions().count(), 0);
+    }
+
+    #[test]
+    fn delivered_count_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        for id in 0..3 {
+            node.broadcast_message(message(id));
+        }
+        assert_eq!(execute_single(&mut node).len(), 3);
+        assert!(node.forget_message(&1));
+        assert_eq!(node.messages().len(), 2);
+        assert_eq!(node.delivered_count(), 3);
+    }
+
+    #[test]
+    fn min_eager_peers_works() {
+        let options = NodeOptions {
+            min_eager_peers: 2,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+
+        node.handle_protocol_message(prune("bar"));
+        assert_eq!(node.eager_push_peers().len(), 2);
+        assert!(node.lazy_push_peers().is_empty());
+
+        node.handle_protocol_message(gossip("bar", 0, 0));
+        node.handle_protocol_message(gossip("baz", 0, 0));
+        assert!(drain_sends(&mut node)
+            .iter()
+            .all(|(_, m)| !matches!(m, ProtocolMessage::Prune(_))));
+        assert_eq!(node.eager_push_peers().len(), 2);
+
+        node.handle_neighbor_up(&"qux".to_owned());
+        node.handle_protocol_message(prune("bar"));
+        assert_eq!(node.eager_push_peers().len(), 2);
+        assert!(node.lazy_push_peers().contains("bar"));
+    }
+
+    #[test]
+    fn drain_actions_works() {
+        let mut polled = Vec::new();
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.broadcast_message(message(0));
+        while let Some(action) = node.poll_action() {
+            polled.push(format!("{:?}", action));
+        }
+
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.broadcast_message(message(0));
+        let drained = node
+            .drain_actions()
+            .map(|a| format!("{:?}", a))
+            .collect::<Vec<_>>();
+        assert_eq!(drained, polled);
+
+        node.broadcast_message(message(1));
+        let mut count = 0;
+        for _ in &mut node {
+            count += 1;
+        }
+        assert_eq!(count, 2);
+        assert!(node.poll_action().is_none());
+    }
+
+    #[test]
+    fn lazy_new_neighbor_mode_works() {
+        let options = NodeOptions {
+            new_neighbor_mode: NewNeighborMode::Lazy,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.broadcast_message(message(0));
+        execute_single(&mut node);
+
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up_many(vec!["baz".to_owned()]);
+        assert!(node.eager_push_peers().is_empty());
+        assert_eq!(node.lazy_push_peers().len(), 2);
+
+        let sends = drain_sends(&mut node);
+        assert_eq!(sends.len(), 2);
+        assert!(sends
+            .iter()
+            .all(|(_, m)| matches!(m, ProtocolMessage::Ihave(m) if m.message_id == 0)));
+    }
+
+    #[test]
+    fn provenance_tracking_works() {
+        let options = NodeOptions {
+            track_provenance: true,
+            ..NodeOptions::default()
+        };
+        let mut nodes: Vec<Node<TestSystem>> = ["foo", "bar", "baz"]
+            .iter()
+            .map(|id| Node::with_options(id.to_string(), options.clone()))
+            .collect();
+        for &(a, b) in &[(0, 1), (1, 2)] {
+            let (a_id, b_id) = (nodes[a].id().clone(), nodes[b].id().clone());
+            nodes[a].handle_neighbor_up(&b_id);
+            nodes[b].handle_neighbor_up(&a_id);
+        }
+
+        nodes[0].broadcast_message(message(0));
+        execute(&mut nodes);
+
+        let p = nodes[0].message_provenance(&0).unwrap();
+        assert_eq!(p.received_from, None);
+        assert_eq!(p.forwarded_to, vec!["bar".to_owned()]);
+
+        let p = nodes[1].message_provenance(&0).unwrap();
+        assert_eq!(p.received_from, Some("foo".to_owned()));
+        assert_eq!(p.forwarded_to, vec!["baz".to_owned()]);
+
+        nodes[1].forget_message(&0);
+        assert!(nodes[1].message_provenance(&0).is_none());
+        assert!(Node::<TestSystem>::new("qux".to_owned())
+            .message_provenance(&0)
+            .is_none());
+    }
+
+    #[cfg(feature = "analysis")]
+    #[test]
+    fn eager_tree_depth_works() {
+        let mut nodes: Vec<Node<TestSystem>> = (0..5).map(|i| Node::new(i.to_string())).collect();
+        for &(a, b) in &[(0, 1), (0, 2), (2, 3), (3, 4)] {
+            nodes[a].handle_neighbor_up(&b.to_string());
+            nodes[b].handle_neighbor_up(&a.to_string());
+        }
+        assert_eq!(analysis::eager_tree_depth(&nodes, &"0".to_owned()), Some(3));
+        assert_eq!(analysis::eager_tree_depth(&nodes, &"2".to_owned()), Some(2));
+        assert_eq!(analysis::eager_tree_depth(&nodes, &"5".to_owned()), None);
+
+        nodes.push(Node::new("5".to_owned()));
+        assert_eq!(analysis::eager_tree_depth(&nodes, &"0".to_owned()), None);
+    }
+
+    #[test]
+    fn ihave_many_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.broadcast_message(message(1));
+        node.drain_actions().count();
+
+        let ihave = IhaveManyMessage::new(&"bar".to_owned(), vec![(0, 1), (1, 1), (2, 3)], true);
+        assert!(node.handle_protocol_message(ihave.into()));
+        assert_eq!(node.waiting_messages(), 2);
+
+        let ihave = IhaveManyMessage::new(&"bar".to_owned(), vec![(3, 0), (4, 0), (5, 0)], false);
+        assert!(node.handle_protocol_message(ihave.into()));
+        assert_eq!(node.waiting_messages(), 5);
+    }
+
+    #[test]
+    fn with_options_and_clock_works() {
+        let mut clock = time::Clock::new();
+        clock.tick(Duration::from_secs(10));
+        let options = NodeOptions::default();
+        let timeout = options.ihave_timeout;
+        let mut node = Node::<TestSystem>::with_options_and_clock("foo".to_owned(), options, clock);
+        assert_eq!(node.clock().now().as_duration(), Duration::from_secs(10));
+
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_protocol_message(ihave("bar", 0, 0, false));
+        assert_eq!(
+            node.next_expiry_time().map(|t| t.as_duration()),
+            Some(Duration::from_secs(10) + timeout)
+        );
+    }
+
+    #[test]
+    fn drop_pending_sends_to_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.broadcast_message(message(0));
+        node.broadcast_message(message(1));
+
+        assert_eq!(node.drop_pending_sends_to(&"bar".to_owned()), 2);
+        assert_eq!(node.drop_pending_sends_to(&"bar".to_owned()), 0);
+
+        let remainings = node.drain_actions().collect::<Vec<_>>();
+        assert_eq!(remainings.len(), 4);
+        assert!(remainings.iter().all(|a| match a {
+            Action::Send { destination, .. } => destination == "baz",
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn payload_transform_works() {
+        struct BytesSystem;
+        impl System for BytesSystem {
+            type NodeId = String;
+            type MessageId = u64;
+            type MessagePayload = Vec<u8>;
+        }
+
+        struct XorTransform;
+        impl PayloadTransform<BytesSystem> for XorTransform {
+            fn store(&self, payload: Vec<u8>) -> Vec<u8> {
+                payload.into_iter().map(|b| b ^ 0xFF).collect()
+            }
+
+            fn load(&self, stored: &Vec<u8>) -> Vec<u8> {
+                stored.iter().map(|b| b ^ 0xFF).collect()
+            }
+        }
+
+        let mut node = Node::<BytesSystem>::new("foo".to_owned());
+        node.set_payload_transform(XorTransform);
+        node.handle_neighbor_up(&"bar".to_owned());
+
+        let gossip = GossipMessage::new(&"bar".to_owned(), Message::new(0, vec![1, 2, 3]), 0);
+        node.handle_protocol_message(gossip.into());
+        assert_eq!(node.messages().get(&0), Some(&vec![0xFE, 0xFD, 0xFC]));
+        assert!(matches!(
+            node.poll_action(),
+            Some(Action::Deliver { message }) if message.payload == vec![1, 2, 3]
+        ));
+
+        let graft = GraftMessage::new(&"bar".to_owned(), Some(0), 0);
+        node.handle_protocol_message(graft.into());
+        assert!(matches!(
+            node.poll_action(),
+            Some(Action::Send { message: ProtocolMessage::Gossip(m), .. })
+                if m.message.payload == vec![1, 2, 3]
+        ));
+    }
+
+    #[test]
+    fn pending_action_count_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        assert_eq!(node.pending_action_count(), 0);
+
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned(), "qux".to_owned()]);
+        node.broadcast_message(message(0));
+        assert_eq!(node.pending_action_count(), 4);
+
+        node.poll_action();
+        assert_eq!(node.pending_action_count(), 3);
+        node.drain_actions().count();
+        assert_eq!(node.pending_action_count(), 0);
+    }
+
+    #[test]
+    fn self_sent_message_is_rejected() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+
+        assert!(!node.handle_protocol_message(gossip("foo", 0, 0)));
+        assert!(!node.handle_protocol_message(prune("foo")));
+        assert_eq!(node.eager_push_peers().len(), 1);
+        assert!(node.eager_push_peers().contains("bar"));
+        assert!(node.lazy_push_peers().is_empty());
+        assert!(node.messages().is_empty());
+        assert!(node.poll_action().is_none());
+    }
+
+    #[test]
+    fn max_grafts_per_cycle_works() {
+        let options = NodeOptions {
+            max_grafts_per_cycle: Some(3),
+            ..NodeOptions::default()
+        };
+        let timeout = options.ihave_timeout;
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        for id in 0..10 {
+            node.handle_protocol_message(ihave("bar", id, 0, false));
+        }
+        node.clock_mut().tick(timeout);
+
+        for &expected in &[3, 3, 3, 1, 0] {
+            let grafts = node
+                .drain_actions()
+                .filter(|a| {
+                    matches!(
+                        a,
+                        Action::Send {
+                            message: ProtocolMessage::Graft(_),
+                            ..
+                        }
+                    )
+                })
+                .count();
+            assert_eq!(grafts, expected);
+        }
+    }
+
+    #[test]
+    fn peer_changes_works() {
+        let options = NodeOptions {
+            track_peer_changes: true,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        let bar = "bar".to_owned();
+        node.handle_neighbor_up(&bar);
+        node.handle_protocol_message(prune("bar"));
+        node.handle_protocol_message(prune("bar"));
+        node.handle_protocol_message(GraftMessage::new(&bar, None, 0).into());
+        node.handle_neighbor_down(&bar);
+        assert_eq!(
+            node.take_peer_changes(),
+            vec![
+                PeerChange::EagerAdded(bar.clone()),
+                PeerChange::EagerRemoved(bar.clone()),
+                PeerChange::LazyAdded(bar.clone()),
+                PeerChange::LazyRemoved(bar.clone()),
+                PeerChange::EagerAdded(bar.clone()),
+                PeerChange::EagerRemoved(bar.clone()),
+            ]
+        );
+        assert!(node.take_peer_changes().is_empty());
+
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&bar);
+        assert!(node.take_peer_changes().is_empty());
+    }
+
+    #[test]
+    fn graft_is_sent_to_nearest_ihave_owner() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.handle_protocol_message(ihave("bar", 0, 4, false));
+        node.handle_protocol_message(ihave("baz", 0, 1, false));
+
+        let timeout = node.options().ihave_timeout;
+        node.clock_mut().tick(timeout);
+        assert!(matches!(
+            node.poll_action(),
+            Some(Action::Send { destination, message: ProtocolMessage::Graft(m) })
+                if destination == "baz" && m.round == 1
+        ));
+        assert!(node.poll_action().is_none());
+
+        node.clock_mut().tick(timeout);
+        assert!(matches!(
+            node.poll_action(),
+            Some(Action::Send { destination, message: ProtocolMessage::Graft(m) })
+                if destination == "bar" && m.round == 4
+        ));
+    }
+
+    #[test]
+    fn stuck_messages_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.handle_protocol_message(ihave("bar", 0, 0, false));
+        node.handle_neighbor_down(&"bar".to_owned());
+
+        let timeout = node.options().ihave_timeout;
+        node.clock_mut().tick(timeout);
+        assert!(node.poll_action().is_none());
+        assert_eq!(node.waiting_messages(), 1);
+
+        assert_eq!(node.stuck_messages(timeout), vec![0]);
+        assert!(node.stuck_messages(timeout * 2).is_empty());
+
+        assert!(node.clear_stuck_messages(timeout * 2).is_empty());
+        assert_eq!(node.clear_stuck_messages(timeout), vec![0]);
+        assert_eq!(node.waiting_messages(), 0);
+    }
+
+    #[test]
+    fn stuck_messages_ignores_messages_with_untried_owners() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned(), "qux".to_owned()]);
+        node.demote_to_lazy(&"bar".to_owned());
+        node.demote_to_lazy(&"baz".to_owned());
+        node.drain_actions().count();
+        node.handle_protocol_message(ihave("bar", 0, 0, false));
+        node.handle_protocol_message(ihave("baz", 0, 1, false));
+
+        let timeout = node.options().ihave_timeout;
+        node.clock_mut().tick(timeout);
+        assert_eq!(drain_sends(&mut node).len(), 1);
+        assert!(node.stuck_messages(timeout).is_empty());
+
+        node.clock_mut().tick(timeout);
+        assert_eq!(drain_sends(&mut node).len(), 1);
+        assert_eq!(node.stuck_messages(timeout), vec![0]);
+    }
+
+    #[test]
+    fn expired_ihaves_are_handled_in_arrival_order() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        let ids = [7, 3, 9, 1, 5, 8, 2];
+        for &id in &ids {
+            node.handle_protocol_message(ihave("bar", id, 0, false));
+        }
+
+        let timeout = node.options().ihave_timeout;
+        node.clock_mut().tick(timeout);
+        let grafted = node
+            .drain_actions()
+            .filter_map(|a| match a {
+                Action::Send {
+                    message: ProtocolMessage::Graft(m),
+                    ..
+                } => m.message_id,
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(grafted, ids);
+    }
+
+    #[test]
+    fn set_peers_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.broadcast_message(message(0));
+        node.handle_neighbor_up(&"qux".to_owned());
+        node.drain_actions().count();
+
+        let set = |ids: &[&str]| ids.iter().map(|s| s.to_string()).collect::<HashSet<_>>();
+        assert!(!node.set_peers(set(&["bar"]), set(&["bar"])));
+        assert!(!node.set_peers(set(&["foo"]), set(&[])));
+        assert!(!node.set_peers(set(&[]), set(&["foo"])));
+        assert_eq!(node.eager_push_peers(), &set(&["qux"]));
+
+        assert!(node.set_peers(set(&["bar", "baz"]), set(&["quux"])));
+        assert_eq!(node.eager_push_peers(), &set(&["bar", "baz"]));
+        assert_eq!(node.lazy_push_peers(), &set(&["quux"]));
+        assert!(node.poll_action().is_none());
+    }
+
+    #[test]
+    fn lazy_suppression_window_works() {
+        let window = Duration::from_millis(100);
+        let options = NodeOptions {
+            lazy_suppression_window: window,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.demote_to_lazy(&"baz".to_owned());
+        node.drain_actions().count();
+
+        node.handle_protocol_message(gossip("bar", 0, 0));
+        node.handle_protocol_message(gossip("bar", 1, 0));
+        assert_eq!(node.drain_actions().count(), 2);
+        assert_eq!(
+            node.next_expiry_time().map(|t| t.as_duration()),
+            Some(window)
+        );
+
+        node.clock_mut().tick(window / 2);
+        node.handle_protocol_message(gossip("baz", 0, 0));
+        node.drain_actions().count();
+
+        node.clock_mut().tick(window / 2);
+        let sends = drain_sends(&mut node);
+        assert_eq!(sends.len(), 1);
+        assert!(matches!(
+            &sends[0],
+            (destination, ProtocolMessage::Ihave(m)) if destination == "baz" && m.message_id == 1
+        ));
+    }
+
+    #[test]
+    fn arc_payload_works() {
+        struct NonClone(u64);
+
+        struct ArcSystem;
+        impl System for ArcSystem {
+            type NodeId = String;
+            type MessageId = u64;
+            type MessagePayload = message::ArcPayload<NonClone>;
+        }
+
+        let mut foo = Node::<ArcSystem>::new("foo".to_owned());
+        let mut bar = Node::<ArcSystem>::new("bar".to_owned());
+        foo.handle_neighbor_up(&"bar".to_owned());
+        bar.handle_neighbor_up(&"foo".to_owned());
+
+        let payload = message::ArcPayload::new(NonClone(123));
+        foo.broadcast_message(Message::new(0, payload.clone()));
+        while let Some(action) = foo.poll_action() {
+            if let Action::Send { message, .. } = action {
+                bar.handle_protocol_message(message);
+            }
+        }
+        match bar.poll_action() {
+            Some(Action::Deliver { message }) => {
+                assert_eq!(message.payload.0, 123);
+                assert!(message::ArcPayload::ptr_eq(&message.payload, &payload));
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn time_until_next_expiry_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        assert_eq!(node.time_until_next_expiry(), None);
+
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_protocol_message(ihave("bar", 0, 0, false));
+        let timeout = node.options().ihave_timeout;
+        assert_eq!(node.time_until_next_expiry(), Some(timeout));
+
+        node.clock_mut().tick(timeout / 2);
+        assert_eq!(node.time_until_next_expiry(), Some(timeout / 2));
+
+        node.clock_mut().tick(timeout);
+        assert_eq!(
+            node.time_until_next_expiry(),
+            Some(Duration::from_millis(0))
+        );
+    }
+
+    #[test]
+    fn delivery_order_works() {
+        for &order in &[DeliveryOrder::BeforeForward, DeliveryOrder::AfterForward] {
+            let options = NodeOptions {
+                delivery_order: order,
+                ..NodeOptions::default()
+            };
+            let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+            node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned()]);
+            node.demote_to_lazy(&"baz".to_owned());
+            node.drain_actions().count();
+
+            node.broadcast_message(message(0));
+            node.handle_protocol_message(gossip("bar", 1, 0));
+            let delivered = node
+                .drain_actions()
+                .map(|a| matches!(a, Action::Deliver { .. }))
+                .collect::<Vec<_>>();
+            if order == DeliveryOrder::BeforeForward {
+                assert_eq!(delivered, [true, false, false, true, false]);
+            } else {
+                assert_eq!(delivered, [false, false, true, false, true]);
+            }
+        }
+    }
+
+    #[test]
+    fn optimization_dry_run_works() {
+        for &dry_run in &[false, true] {
+            let options = NodeOptions {
+                optimization_dry_run: dry_run,
+                ..NodeOptions::default()
+            };
+            let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+            node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned()]);
+            node.demote_to_lazy(&"baz".to_owned());
+            node.drain_actions().count();
+
+            node.handle_protocol_message(ihave("baz", 0, 0, false));
+            node.handle_protocol_message(gossip("bar", 0, 2));
+            assert_eq!(node.optimization_count(), 1);
+
+            let sends = drain_sends(&mut node);
+            let grafts = sends
+                .iter()
+                .filter(|(d, m)| d == "baz" && matches!(m, ProtocolMessage::Graft(_)))
+                .count();
+            let prunes = sends
+                .iter()
+                .filter(|(d, m)| d == "bar" && matches!(m, ProtocolMessage::Prune(_)))
+                .count();
+            if dry_run {
+                assert_eq!((grafts, prunes), (0, 0));
+            } else {
+                assert_eq!((grafts, prunes), (1, 1));
+            }
+            assert!(node.eager_push_peers().contains("bar"));
+            assert!(node.lazy_push_peers().contains("baz"));
+        }
+    }
+
+    #[test]
+    fn payload_conflict_detection_works() {
+        struct BytesSystem;
+        impl System for BytesSystem {
+            type NodeId = String;
+            type MessageId = u64;
+            type MessagePayload = Vec<u8>;
+        }
+        let gossip = |sender: &str, payload: Vec<u8>| {
+            GossipMessage::<BytesSystem>::new(&sender.to_owned(), Message::new(0, payload), 0)
+        };
+
+        let mut node = Node::<BytesSystem>::new("foo".to_owned());
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned()]);
+        node.handle_protocol_message(gossip("bar", vec![1]).into());
+        node.handle_protocol_message(gossip("baz", vec![2]).into());
+        assert_eq!(node.payload_conflict_count(), 0);
+
+        node.detect_payload_conflicts(true);
+        node.handle_protocol_message(gossip("bar", vec![1]).into());
+        assert_eq!(node.payload_conflict_count(), 0);
+        node.handle_protocol_message(gossip("bar", vec![2]).into());
+        assert_eq!(node.payload_conflict_count(), 1);
+    }
+
+    #[test]
+    fn shutdown_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned(), "qux".to_owned()]);
+        node.demote_to_lazy(&"qux".to_owned());
+        node.handle_protocol_message(ihave("qux", 1, 0, false));
+        node.broadcast_message(message(0));
+        node.drain_actions().count();
+
+        let actions = node.shutdown();
+        let mut pruned = actions
+            .iter()
+            .filter_map(|a| match a {
+                Action::Send {
+                    destination,
+                    message: ProtocolMessage::Prune(_),
+                } => Some(destination.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        pruned.sort();
+        assert_eq!(actions.len(), 2);
+        assert_eq!(pruned, ["bar", "baz"]);
+
+        assert!(node.eager_push_peers().is_empty());
+        assert!(node.lazy_push_peers().is_empty());
+        assert!(node.messages().is_empty());
+        assert_eq!(node.waiting_messages(), 0);
+        assert!(node.poll_action().is_none());
+    }
+
+    #[test]
+    fn max_graft_owners_per_message_works() {
+        let options = NodeOptions {
+            ihave_timeout: Duration::from_millis(100),
+            max_graft_owners_per_message: Some(1),
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned(), "qux".to_owned()]);
+        for peer in &["bar", "baz", "qux"] {
+            node.handle_protocol_message(ihave(peer, 0, 0, false));
+        }
+        let mut grafted = Vec::new();
+        let mut tick = |node: &mut Node<TestSystem>, millis| {
+            node.clock_mut().tick(Duration::from_millis(millis));
+            for (destination, message) in drain_sends(node) {
+                if let ProtocolMessage::Graft(_) = message {
+                    grafted.push(destination);
+                }
+            }
+            grafted.clone()
+        };
+
+        // The expiries of "bar" (100ms) and "baz" (200ms) are handled at once,
+        // but only the first owner is grafted
+        assert_eq!(tick(&mut node, 250), ["bar"]);
+        assert_eq!(tick(&mut node, 50), ["bar"]);
+
+        // The GRAFT to "bar" timed out
+        assert_eq!(tick(&mut node, 50), ["bar", "baz"]);
+
+        // Removing the grafted node makes the deferred owner eligible
+        node.handle_neighbor_down(&"baz".to_owned());
+        assert_eq!(tick(&mut node, 0), ["bar", "baz", "qux"]);
+        assert_eq!(tick(&mut node, 1000), ["bar", "baz", "qux"]);
+    }
+
+    #[test]
+    fn forwarded_eagerly_works() {
+        let mut nodes: Vec<Node<TestSystem>> = vec![
+            Node::new("foo".to_owned()),
+            Node::new("bar".to_owned()),
+            Node::new("baz".to_owned()),
+        ];
+        for edges in &[
+            ("foo".to_owned(), "bar".to_owned()),
+            ("bar".to_owned(), "baz".to_owned()),
+        ][..]
+        {
+            get(&mut nodes, &edges.0).handle_neighbor_up(&edges.1);
+            get(&mut nodes, &edges.1).handle_neighbor_up(&edges.0);
+        }
+
+        nodes[0].broadcast_message(message(0));
+        execute(&mut nodes);
+        assert!(nodes[0].forwarded_eagerly(&0));
+        assert!(nodes[1].forwarded_eagerly(&0));
+        assert!(!nodes[2].forwarded_eagerly(&0));
+        assert!(!nodes[1].forwarded_eagerly(&1));
+
+        nodes[1].forget_message(&0);
+        assert!(!nodes[1].forwarded_eagerly(&0));
+    }
+
+    #[test]
+    fn probe_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        drain_sends(&mut node);
+
+        assert_eq!(node.probe_peer(&"baz".to_owned()), None);
+        assert_eq!(node.probe_peer(&"bar".to_owned()), Some(0));
+        match &drain_sends(&mut node)[..] {
+            [(d, ProtocolMessage::Probe(m))] => assert_eq!((d.as_str(), m.nonce), ("bar", 0)),
+            sends => panic!("{:?}", sends),
+        }
+
+        // A probe elicits an ack sent back to the prober
+        let probe = ProbeMessage {
+            sender: "bar".to_owned(),
+            nonce: 10,
+        };
+        assert!(node.handle_protocol_message(probe.into()));
+        match &drain_sends(&mut node)[..] {
+            [(d, ProtocolMessage::ProbeAck(m))] => {
+                assert_eq!((d.as_str(), m.sender.as_str(), m.nonce), ("bar", "foo", 10))
+            }
+            sends => panic!("{:?}", sends),
+        }
+
+        // Probing does not affect the tree
+        let ack = ProbeAckMessage {
+            sender: "bar".to_owned(),
+            nonce: 0,
+        };
+        assert!(node.handle_protocol_message(ack.into()));
+        assert!(drain_sends(&mut node).is_empty());
+        assert!(node.eager_push_peers().contains("bar"));
+    }
+
+    #[test]
+    fn max_waiting_messages_works() {
+        let options = NodeOptions {
+            max_waiting_messages: Some(2),
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned()]);
+        for id in 0..5 {
+            node.handle_protocol_message(ihave("bar", id, 0, false));
+        }
+        assert_eq!(node.waiting_messages(), 2);
+        assert_eq!(node.dropped_ihave_count(), 3);
+
+        // IHAVEs for the tracked messages are still accepted
+        node.handle_protocol_message(ihave("baz", 1, 0, false));
+        assert_eq!(node.timeout_queue_len(), 3);
+        assert_eq!(node.dropped_ihave_count(), 3);
+    }
+
+    #[test]
+    fn pending_lazy_announcements_works() {
+        let options = NodeOptions {
+            lazy_suppression_window: Duration::from_millis(100),
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.demote_to_lazy(&"baz".to_owned());
+        node.drain_actions().count();
+
+        node.handle_protocol_message(gossip("bar", 0, 0));
+        node.handle_protocol_message(gossip("bar", 1, 2));
+        node.drain_actions().count();
+        let pendings = node
+            .pending_lazy_announcements(&"baz".to_owned())
+            .map(|(id, round)| (*id, round))
+            .collect::<Vec<_>>();
+        assert_eq!(pendings, [(0, 1), (1, 3)]);
+        assert_eq!(
+            node.pending_lazy_announcements(&"bar".to_owned()).count(),
+            0
+        );
+
+        // Suppressed announcements are not listed
+        node.handle_protocol_message(ihave("baz", 0, 0, false));
+        let pendings = node
+            .pending_lazy_announcements(&"baz".to_owned())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        assert_eq!(pendings, [1]);
+    }
+
+    #[test]
+    fn forgotten_message_memory_works() {
+        let options = NodeOptions {
+            forgotten_message_memory: 1,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.drain_actions().count();
+
+        node.handle_protocol_message(gossip("bar", 0, 0));
+        assert_eq!(node.drain_actions().count(), 2); // DELIVER and GOSSIP to "baz"
+        assert!(node.forget_message(&0));
+
+        // The replayed GOSSIP is handled as a duplicate
+        node.handle_protocol_message(gossip("baz", 0, 0));
+        let actions = node.drain_actions().collect::<Vec<_>>();
+        assert!(matches!(
+            &actions[..],
+            [Action::Send { destination, message: ProtocolMessage::Prune(_) }] if destination == "baz"
+        ));
+        node.handle_protocol_message(ihave("baz", 0, 0, false));
+        assert_eq!(node.waiting_messages(), 0);
+
+        // Only the most recently forgotten message is remembered
+        node.handle_protocol_message(gossip("bar", 1, 0));
+        node.drain_actions().count();
+        assert!(node.forget_message(&1));
+        node.handle_protocol_message(gossip("bar", 0, 0));
+        assert!(node
+            .drain_actions()
+            .any(|a| matches!(a, Action::Deliver { message } if message.id == 0)));
+    }
+
+    #[test]
+    fn recent_delivery_rate_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.broadcast_message(message(0));
+        assert_eq!(node.recent_delivery_rate(Duration::from_secs(1)), 0.0);
+
+        node.options_mut().delivery_rate_window = Duration::from_secs(1);
+        for id in 1..4 {
+            node.broadcast_message(message(id));
+        }
+        node.clock_mut().tick(Duration::from_millis(500));
+        for id in 4..6 {
+            node.broadcast_message(message(id));
+        }
+        assert_eq!(node.recent_delivery_rate(Duration::from_secs(1)), 5.0);
+        assert_eq!(node.recent_delivery_rate(Duration::from_secs(10)), 5.0);
+        assert_eq!(node.recent_delivery_rate(Duration::from_millis(500)), 4.0);
+
+        node.clock_mut().tick(Duration::from_millis(600));
+        assert_eq!(node.recent_delivery_rate(Duration::from_secs(1)), 2.0);
+        assert_eq!(node.recent_delivery_rate(Duration::from_millis(500)), 0.0);
+    }
+
+    #[test]
+    fn validate_invariants_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_protocol_message(ihave("bar", 0, 0, false));
+        assert_eq!(node.validate_invariants(), Ok(()));
+
+        node.lazy_push_peers_mut().insert("bar".to_owned());
+        assert!(node.validate_invariants().is_err());
+
+        node.lazy_push_peers_mut().remove("bar");
+        node.lazy_push_peers_mut().insert("foo".to_owned());
+        assert!(node.validate_invariants().is_err());
+    }
+
+    #[test]
+    fn request_message_works() {
+        let mut nodes: Vec<Node<TestSystem>> =
+            vec![Node::new("foo".to_owned()), Node::new("bar".to_owned())];
+        nodes[0].broadcast_message(message(0));
+        execute(&mut nodes);
+        nodes[0].handle_neighbor_up(&"bar".to_owned());
+        nodes[1].handle_neighbor_up(&"foo".to_owned());
+        assert!(!nodes[1].request_message(&"baz".to_owned(), 0));
+
+        // "bar" has received no IHAVE for the message
+        nodes[0].drain_actions().count();
+        assert_eq!(nodes[1].waiting_messages(), 0);
+        assert!(nodes[1].request_message(&"foo".to_owned(), 0));
+        execute(&mut nodes);
+        assert_eq!(nodes[1].messages().get(&0), Some(&()));
+        assert!(!nodes[1].request_message(&"foo".to_owned(), 0));
+    }
+
+    #[test]
+    fn no_peer_receives_both_gossip_and_ihave() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        for peer in &["bar", "baz", "qux", "quux"] {
+            node.handle_neighbor_up(&peer.to_string());
+        }
+        node.demote_to_lazy(&"qux".to_owned());
+        node.demote_to_lazy(&"quux".to_owned());
+        node.drain_actions().count();
+
+        // The sender is moved from the lazy push peers to the eager push peers
+        node.handle_protocol_message(gossip("qux", 0, 0));
+        let sends = drain_sends(&mut node);
+        let gossips = sends
+            .iter()
+            .filter(|(_, m)| matches!(m, ProtocolMessage::Gossip(_)))
+            .map(|(d, _)| d.as_str())
+            .collect::<HashSet<_>>();
+        let ihaves = sends
+            .iter()
+            .filter(|(_, m)| matches!(m, ProtocolMessage::Ihave(_)))
+            .map(|(d, _)| d.as_str())
+            .collect::<HashSet<_>>();
+        assert_eq!(gossips, ["bar", "baz"].iter().copied().collect());
+        assert_eq!(ihaves, ["quux"].iter().copied().collect());
+        assert!(node.eager_push_peers().contains("qux"));
+
+        node.broadcast_message(message(1));
+        let sends = drain_sends(&mut node);
+        for peer in &["bar", "baz", "qux", "quux"] {
+            assert_eq!(sends.iter().filter(|(d, _)| d == peer).count(), 1);
+        }
+    }
+
+    #[test]
+    fn approx_memory_bytes_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.set_payload_size(|_| 100);
+        node.handle_neighbor_up(&"bar".to_owned());
+        let base = node.approx_memory_bytes();
+
+        for id in 0..10 {
+            node.broadcast_message(message(id));
+        }
+        let ten = node.approx_memory_bytes();
+        for id in 10..20 {
+            node.broadcast_message(message(id));
+        }
+        let twenty = node.approx_memory_bytes();
+        assert_eq!(ten - base, 10 * (100 + std::mem::size_of::<u64>()));
+        assert_eq!(twenty - base, 2 * (ten - base));
+    }
+
+    #[test]
+    fn graft_response_has_actual_round() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.broadcast_message(message(0));
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_protocol_message(gossip("bar", 1, 3));
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.drain_actions().count();
+
+        for id in 0..2 {
+            let graft = GraftMessage {
+                sender: "baz".to_owned(),
+                message_id: Some(id),
+                round: 9,
+            };
+            node.handle_protocol_message(graft.into());
+        }
+        let rounds = drain_sends(&mut node)
+            .into_iter()
+            .filter_map(|(_, m)| match m {
+                ProtocolMessage::Gossip(m) => Some((m.message.id, m.round)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rounds, [(0, 1), (1, 4)]);
+    }
+
+    #[test]
+    fn is_originator_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.broadcast_message(message(1));
+        node.handle_protocol_message(gossip("bar", 2, 0));
+        assert!(node.is_originator(&1));
+        assert!(!node.is_originator(&2));
+        assert!(!node.is_originator(&3));
+
+        node.forget_message(&1);
+        assert!(!node.is_originator(&1));
+    }
+
+    #[test]
+    fn duplicate_tolerance_works() {
+        let options = NodeOptions {
+            duplicate_tolerance: 2,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.drain_actions().count();
+
+        let pruned = |node: &mut Node<TestSystem>| {
+            drain_sends(node)
+                .iter()
+                .any(|(d, m)| d == "baz" && matches!(m, ProtocolMessage::Prune(_)))
+        };
+        for id in 0..2 {
+            node.handle_protocol_message(gossip("bar", id, 0));
+            node.handle_protocol_message(gossip("baz", id, 0));
+            assert!(!pruned(&mut node));
+            assert!(node.eager_push_peers().contains("baz"));
+        }
+
+        node.handle_protocol_message(gossip("bar", 2, 0));
+        node.handle_protocol_message(gossip("baz", 2, 0));
+        assert!(pruned(&mut node));
+        assert!(node.lazy_push_peers().contains("baz"));
+    }
+
+    #[test]
+    fn export_and_import_state_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.demote_to_lazy(&"baz".to_owned());
+        node.broadcast_message(message(0));
+        node.handle_protocol_message(gossip("bar", 1, 0));
+        node.handle_protocol_message(ihave("baz", 2, 3, false));
+        node.clock_mut().tick(Duration::from_millis(10));
+        node.drain_actions().count();
+
+        let state = node.export_state();
+        assert_eq!(state.version(), NodeState::<TestSystem>::VERSION);
+        let imported = Node::import_state(state.clone()).unwrap();
+        assert_eq!(imported.id(), node.id());
+        assert_eq!(imported.clock().now(), node.clock().now());
+        assert_eq!(imported.eager_push_peers(), node.eager_push_peers());
+        assert_eq!(imported.lazy_push_peers(), node.lazy_push_peers());
+        assert_eq!(imported.messages(), node.messages());
+        assert_eq!(imported.waiting_messages(), 1);
+        assert!(imported.is_originator(&0));
+        assert!(!imported.is_originator(&1));
+        assert_eq!(imported.messages_by_round(), node.messages_by_round());
+        assert_eq!(imported.messages_by_round(), [(&0, 0), (&1, 0)]);
+    }
+
+    #[test]
+    fn import_state_restores_rounds_of_received_messages() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_protocol_message(gossip("bar", 1, 4));
+
+        let imported = Node::import_state(node.export_state()).unwrap();
+        assert_eq!(imported.messages_by_round(), [(&1, 4)]);
+    }
+
+    #[test]
+    fn import_state_rejects_invalid_peers() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+
+        let mut state = node.export_state();
+        state.lazy_push_peers.push("bar".to_owned());
+        assert!(Node::import_state(state).is_err());
+
+        let mut state = node.export_state();
+        state.eager_push_peers.push("foo".to_owned());
+        assert!(Node::import_state(state).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_node_state_works() {
+        #[derive(Debug)]
+        struct SerdeSystem;
+        impl System for SerdeSystem {
+            type NodeId = String;
+            type MessageId = u64;
+            type MessagePayload = String;
+        }
+
+        let mut node = Node::<SerdeSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.broadcast_message(Message::new(0, "hello".to_owned()));
+
+        let json = serde_json::to_string(&node.export_state()).unwrap();
+        let state: NodeState<SerdeSystem> = serde_json::from_str(&json).unwrap();
+        let imported = Node::import_state(state).unwrap();
+        assert_eq!(imported.id(), node.id());
+        assert_eq!(imported.eager_push_peers(), node.eager_push_peers());
+        assert_eq!(imported.messages(), node.messages());
+        assert_eq!(
+            imported.options().ihave_timeout,
+            node.options().ihave_timeout
+        );
+        assert!(imported.is_originator(&0));
+
+        // Snapshots of unknown versions are rejected
+        let json = json.replacen("\"version\":1", "\"version\":100", 1);
+        let state: NodeState<SerdeSystem> = serde_json::from_str(&json).unwrap();
+        assert!(Node::import_state(state).is_err());
+    }
+
+    #[test]
+    fn shared_clock_works() {
+        let shared = time::SharedClock::new();
+        let mut nodes: Vec<Node<TestSystem>> =
+            vec![Node::new("foo".to_owned()), Node::new("bar".to_owned())];
+        for node in &mut nodes {
+            node.handle_neighbor_up(&"baz".to_owned());
+            node.drain_actions().count();
+            node.handle_protocol_message(ihave("baz", 0, 0, false));
+        }
+        let timeout = nodes[0].options().ihave_timeout;
+        let rates = [1.0, 0.5];
+
+        let graft_senders = |nodes: &mut [Node<TestSystem>]| {
+            let mut senders = Vec::new();
+            for (node, rate) in nodes.iter_mut().zip(rates.iter()) {
+                shared.sync(node.clock_mut(), *rate);
+                if drain_sends(node)
+                    .iter()
+                    .any(|(_, m)| matches!(m, ProtocolMessage::Graft(_)))
+                {
+                    senders.push(node.id().clone());
+                }
+            }
+            senders
+        };
+
+        // The IHAVE timeout fires based on the own clock of each node
+        shared.tick(timeout);
+        assert_eq!(graft_senders(&mut nodes), ["foo"]);
+        shared.tick(timeout);
+        assert_eq!(graft_senders(&mut nodes), ["bar"]);
+    }
+
+    #[test]
+    fn max_payload_size_works() {
+        struct SizedSystem;
+        impl System for SizedSystem {
+            type NodeId = String;
+            type MessageId = u64;
+            type MessagePayload = Vec<u8>;
+        }
+
+        let options = NodeOptions {
+            max_payload_size: Some(10),
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<SizedSystem>::with_options("foo".to_owned(), options);
+        node.set_payload_size(|p| p.len());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.drain_actions().count();
+
+        let gossip = |id, size| {
+            let message = Message::new(id, vec![0; size]);
+            ProtocolMessage::Gossip(GossipMessage::new(&"bar".to_owned(), message, 0))
+        };
+        node.handle_protocol_message(gossip(0, 10));
+        node.handle_protocol_message(gossip(1, 11));
+        node.broadcast_message(Message::new(2, vec![0; 100]));
+        assert_eq!(node.messages().len(), 1);
+        assert!(node.messages().contains_key(&0));
+        assert_eq!(node.rejected_payload_count(), 2);
+        assert_eq!(node.delivered_count(), 1);
+    }
+
+    #[test]
+    fn peers_that_pruned_us_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.handle_protocol_message(prune("bar"));
+        assert_eq!(node.peers_that_pruned_us().collect::<Vec<_>>(), ["bar"]);
+
+        node.handle_protocol_message(gossip("bar", 0, 0));
+        assert_eq!(node.peers_that_pruned_us().count(), 0);
+
+        node.handle_protocol_message(prune("baz"));
+        node.handle_neighbor_down(&"baz".to_owned());
+        assert_eq!(node.peers_that_pruned_us().count(), 0);
+    }
+
+    #[test]
+    fn set_ihave_timeout_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.set_ihave_timeout(Duration::from_millis(100));
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.drain_actions().count();
+        node.handle_protocol_message(ihave("bar", 0, 0, false));
+        node.handle_protocol_message(ihave("baz", 0, 0, false));
+        node.clock_mut().tick(Duration::from_millis(20));
+        assert_eq!(
+            node.time_until_next_expiry(),
+            Some(Duration::from_millis(80))
+        );
+
+        node.set_ihave_timeout(Duration::from_millis(50));
+        assert_eq!(node.options().ihave_timeout, Duration::from_millis(50));
+        assert_eq!(
+            node.time_until_next_expiry(),
+            Some(Duration::from_millis(40))
+        );
+        node.clock_mut().tick(Duration::from_millis(40));
+        assert_eq!(drain_sends(&mut node).len(), 1);
+
+        // The expiry of the second owner is also rescaled (from 200ms to 110ms)
+        node.clock_mut().tick(Duration::from_millis(50));
+        assert_eq!(
+            node.time_until_next_expiry(),
+            Some(Duration::from_millis(0))
+        );
+    }
+
+    #[test]
+    fn lazy_fanout_works() {
+        let options = NodeOptions {
+            new_neighbor_mode: NewNeighborMode::Lazy,
+            lazy_fanout: Some(3),
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.set_rng(SplitMix64::new(0));
+        for i in 0..10 {
+            node.handle_neighbor_up(&i.to_string());
+        }
+        assert_eq!(node.lazy_push_peers().len(), 10);
+        node.drain_actions().count();
+
+        let mut destinations = HashSet::new();
+        for id in 0..10 {
+            node.broadcast_message(message(id));
+            let sends = drain_sends(&mut node);
+            assert_eq!(sends.len(), 3);
+            assert!(sends
+                .iter()
+                .all(|(_, m)| matches!(m, ProtocolMessage::Ihave(_))));
+            destinations.extend(sends.into_iter().map(|(d, _)| d));
+        }
+        assert!(destinations.len() > 3);
+    }
+
+    #[test]
+    fn neighbor_count_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned(), "qux".to_owned()]);
+        assert_eq!(
+            (node.neighbor_count(), node.eager_count(), node.lazy_count()),
+            (3, 3, 0)
+        );
+
+        node.handle_protocol_message(prune("bar"));
+        assert_eq!(
+            (node.neighbor_count(), node.eager_count(), node.lazy_count()),
+            (3, 2, 1)
+        );
+
+        node.handle_neighbor_down(&"baz".to_owned());
+        assert_eq!(
+            (node.neighbor_count(), node.eager_count(), node.lazy_count()),
+            (2, 1, 1)
+        );
+    }
+
+    #[test]
+    fn retract_message_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.broadcast_message(message(0));
+        node.drain_actions().count();
+        assert!(node.retract_message(&0));
+        assert!(!node.retract_message(&0));
+        assert!(node.messages().is_empty());
+
+        let graft = GraftMessage {
+            sender: "bar".to_owned(),
+            message_id: Some(0),
+            round: 0,
+        };
+        node.handle_protocol_message(graft.into());
+        let sends = drain_sends(&mut node);
+        assert!(matches!(
+            &sends[..],
+            [(d, ProtocolMessage::Prune(_))] if d == "bar"
+        ));
+    }
+
+    #[test]
+    fn rebroadcast_cancels_retraction() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.broadcast_message(message(0));
+        assert!(node.retract_message(&0));
+        node.broadcast_message(message(0));
+        node.drain_actions().count();
+
+        let graft = GraftMessage {
+            sender: "bar".to_owned(),
+            message_id: Some(0),
+            round: 0,
+        };
+        node.handle_protocol_message(graft.into());
+        let sends = drain_sends(&mut node);
+        assert!(matches!(
+            &sends[..],
+            [(d, ProtocolMessage::Gossip(m))] if d == "bar" && m.message.id == 0
+        ));
+    }
+
+    #[test]
+    fn retracted_message_memory_works() {
+        let options = NodeOptions {
+            retracted_message_memory: 1,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.broadcast_message(message(0));
+        node.broadcast_message(message(1));
+        assert!(node.retract_message(&0));
+        assert!(node.retract_message(&1));
+        node.drain_actions().count();
+
+        for id in 0..2 {
+            let graft = GraftMessage {
+                sender: "bar".to_owned(),
+                message_id: Some(id),
+                round: 0,
+            };
+            node.handle_protocol_message(graft.into());
+        }
+        let prunes = drain_sends(&mut node)
+            .iter()
+            .filter(|(_, m)| matches!(m, ProtocolMessage::Prune(_)))
+            .count();
+        assert_eq!(prunes, 1);
+    }
+
+    #[test]
+    fn suggest_forget_works() {
+        let options = NodeOptions {
+            suggest_forget: true,
+            ..NodeOptions::default()
+        };
+        let mut nodes: Vec<Node<TestSystem>> = ["foo", "bar", "baz"]
+            .iter()
+            .map(|id| Node::with_options(id.to_string(), options.clone()))
+            .collect();
+        for edges in &[("foo", "bar"), ("bar", "baz"), ("baz", "foo")] {
+            get(&mut nodes, &edges.0.to_owned()).handle_neighbor_up(&edges.1.to_owned());
+            get(&mut nodes, &edges.1.to_owned()).handle_neighbor_up(&edges.0.to_owned());
+        }
+        nodes[0].broadcast_message(message(0));
+        assert!(matches!(
+            nodes[0].peek_actions().last(),
+            Some(Action::ForgetSuggested { message_id: 0 })
+        ));
+        execute(&mut nodes);
+
+        // A lazy push peer has to announce the message
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.demote_to_lazy(&"baz".to_owned());
+        node.broadcast_message(message(0));
+        let suggested = |node: &mut Node<TestSystem>| {
+            node.drain_actions()
+                .filter(|a| matches!(a, Action::ForgetSuggested { .. }))
+                .count()
+        };
+        assert_eq!(suggested(&mut node), 0);
+        node.handle_protocol_message(ihave("baz", 0, 0, false));
+        assert_eq!(suggested(&mut node), 1);
+        node.handle_protocol_message(ihave("baz", 0, 0, false));
+        assert_eq!(suggested(&mut node), 0);
+    }
+
+    #[test]
+    fn protocol_message_id_works() {
+        assert_eq!(gossip("foo", 1, 0).message_id(), Some(&1));
+        assert_eq!(ihave("foo", 2, 0, false).message_id(), Some(&2));
+        assert_eq!(prune("foo").message_id(), None);
+
+        let graft = |message_id| {
+            ProtocolMessage::<TestSystem>::from(GraftMessage {
+                sender: "foo".to_owned(),
+                message_id,
+                round: 0,
+            })
+        };
+        assert_eq!(graft(Some(3)).message_id(), Some(&3));
+        assert_eq!(graft(None).message_id(), None);
+    }
+
+    #[test]
+    fn protocol_message_round_works() {
+        assert_eq!(gossip("foo", 1, 3).round(), Some(3));
+        assert_eq!(ihave("foo", 2, 4, false).round(), Some(4));
+        assert_eq!(prune("foo").round(), None);
+
+        let graft = ProtocolMessage::<TestSystem>::from(GraftMessage {
+            sender: "foo".to_owned(),
+            message_id: Some(3),
+            round: 5,
+        });
+        assert_eq!(graft.round(), Some(5));
+    }
+
+    #[test]
+    fn round_histogram_works() {
+        let options = NodeOptions {
+            track_round_histogram: true,
+            ..NodeOptions::default()
+        };
+        let mut nodes: Vec<Node<TestSystem>> = (0..4)
+            .map(|i| Node::with_options(i.to_string(), options.clone()))
+            .collect();
+        for i in 0..3 {
+            let (a, b) = (i.to_string(), (i + 1).to_string());
+            get(&mut nodes, &a).handle_neighbor_up(&b);
+            get(&mut nodes, &b).handle_neighbor_up(&a);
+        }
+        nodes[0].broadcast_message(message(0));
+        nodes[0].broadcast_message(message(1));
+        execute(&mut nodes);
+
+        for (i, node) in nodes.iter().enumerate() {
+            let expected = [(i as u16, 2)].iter().copied().collect::<HashMap<_, _>>();
+            assert_eq!(node.round_histogram(), &expected);
+        }
+    }
+
+    #[test]
+    fn pull_only_works() {
+        let options = NodeOptions {
+            pull_only: true,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        assert!(!node.promote_to_eager(&"bar".to_owned()));
+        node.drain_actions().count();
+
+        node.handle_protocol_message(gossip("bar", 0, 0));
+        node.broadcast_message(message(1));
+        let graft = GraftMessage {
+            sender: "baz".to_owned(),
+            message_id: None,
+            round: 0,
+        };
+        node.handle_protocol_message(graft.into());
+        node.handle_protocol_message(ihave("bar", 2, 0, false));
+        let timeout = node.options().ihave_timeout;
+        node.clock_mut().tick(timeout);
+
+        let sends = drain_sends(&mut node);
+        assert!(!sends
+            .iter()
+            .any(|(_, m)| matches!(m, ProtocolMessage::Gossip(_))));
+        assert_eq!(
+            sends
+                .iter()
+                .filter(|(_, m)| matches!(m, ProtocolMessage::Ihave(_)))
+                .count(),
+            3
+        );
+        assert!(sends
+            .iter()
+            .any(|(d, m)| d == "bar" && matches!(m, ProtocolMessage::Graft(_))));
+        assert!(node.eager_push_peers().is_empty());
+    }
+
+    #[test]
+    fn handle_protocol_message_ref_works() {
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        struct CountingPayload;
+        impl Clone for CountingPayload {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::SeqCst);
+                CountingPayload
+            }
+        }
+
+        struct CountingSystem;
+        impl System for CountingSystem {
+            type NodeId = String;
+            type MessageId = u64;
+            type MessagePayload = CountingPayload;
+        }
+
+        let mut node = Node::<CountingSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        let gossip = |sender: &str| {
+            let message = Message::new(0, CountingPayload);
+            ProtocolMessage::Gossip(GossipMessage::new(&sender.to_owned(), message, 0))
+        };
+
+        assert!(node.handle_protocol_message_ref(&gossip("bar")));
+        assert!(CLONES.load(Ordering::SeqCst) > 0);
+        node.drain_actions().count();
+
+        // A duplicate GOSSIP is handled without cloning the payload
+        let before = CLONES.load(Ordering::SeqCst);
+        assert!(node.handle_protocol_message_ref(&gossip("baz")));
+        assert_eq!(CLONES.load(Ordering::SeqCst), before);
+        assert!(node.lazy_push_peers().contains("baz"));
+        assert!(!node.handle_protocol_message_ref(&gossip("qux")));
+    }
+
+    #[test]
+    fn capacity_hints_work() {
+        let options = NodeOptions {
+            expected_messages: 128,
+            expected_peers: 8,
+            ..NodeOptions::default()
+        };
+        let mut nodes: Vec<Node<TestSystem>> = ["foo", "bar", "baz", "qux"]
+            .iter()
+            .map(|id| Node::with_options((*id).to_owned(), options.clone()))
+            .collect();
+        let foo = "foo".to_owned();
+        for peer in &["bar".to_owned(), "baz".to_owned(), "qux".to_owned()] {
+            get(&mut nodes, &foo).handle_neighbor_up(peer);
+            get(&mut nodes, peer).handle_neighbor_up(&foo);
+        }
+
+        for id in 0..100 {
+            nodes[0].broadcast_message(message(id));
+        }
+        execute(&mut nodes);
+        for node in &nodes {
+            assert_eq!(node.messages().len(), 100);
+        }
+    }
+
+    #[test]
+    fn handle_error_works() {
+        let options = NodeOptions {
+            removed_peer_memory: 1,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        assert_eq!(
+            node.try_handle_protocol_message(gossip("bar", 0, 0)),
+            Ok(())
+        );
+
+        node.handle_neighbor_down(&"bar".to_owned());
+        assert_eq!(
+            node.try_handle_protocol_message(gossip("bar", 1, 0)),
+            Err(HandleError::RemovedSender)
+        );
+        assert_eq!(
+            node.try_handle_protocol_message(gossip("qux", 1, 0)),
+            Err(HandleError::UnknownSender)
+        );
+        assert_eq!(
+            node.try_handle_protocol_message(gossip("foo", 1, 0)),
+            Err(HandleError::OwnMessage)
+        );
+
+        // Only the most recently removed peers are remembered
+        node.handle_neighbor_down(&"baz".to_owned());
+        assert_eq!(
+            node.try_handle_protocol_message(gossip("bar", 1, 0)),
+            Err(HandleError::UnknownSender)
+        );
+        assert_eq!(
+            node.try_handle_protocol_message(gossip("baz", 1, 0)),
+            Err(HandleError::RemovedSender)
+        );
+    }
+
+    #[test]
+    fn resync_order_works() {
+        for order in &[ResyncOrder::OldestFirst, ResyncOrder::NewestFirst] {
+            let options = NodeOptions {
+                resync_order: *order,
+                ..NodeOptions::default()
+            };
+            let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+            for id in &[3, 0, 4, 1, 2] {
+                node.broadcast_message(message(*id));
+            }
+
+            node.handle_neighbor_up(&"bar".to_owned());
+            let ids = drain_sends(&mut node)
+                .into_iter()
+                .map(|(_, m)| match m {
+                    ProtocolMessage::Ihave(m) => m.message_id,
+                    _ => panic!(),
+                })
+                .collect::<Vec<_>>();
+            let mut expected = vec![3, 0, 4, 1, 2];
+            if *order == ResyncOrder::NewestFirst {
+                expected.reverse();
+            }
+            assert_eq!(ids, expected);
+        }
+    }
+
+    #[test]
+    fn is_idle_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        assert!(node.is_idle());
+
+        node.broadcast_message(message(0));
+        assert!(!node.is_idle());
+        while node.poll_action().is_some() {}
+        assert!(node.is_idle());
+
+        // A waiting `IHAVE` message keeps the node busy
+        node.handle_protocol_message(prune("bar"));
+        node.handle_protocol_message(ihave("bar", 1, 0, false));
+        while node.poll_action().is_some() {}
+        assert!(!node.is_idle());
+
+        node.handle_protocol_message(gossip("bar", 1, 0));
+        while node.poll_action().is_some() {}
+        assert!(node.is_idle());
+    }
+
+    #[test]
+    fn max_backup_owners_works() {
+        let options = NodeOptions {
+            max_backup_owners: Some(3),
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        let peers = (0..10).map(|i| format!("peer{}", i)).collect::<Vec<_>>();
+        for peer in &peers {
+            node.handle_neighbor_up(peer);
+        }
+        for (i, peer) in peers.iter().enumerate() {
+            node.handle_protocol_message(ihave(peer, 0, 10 - i as u16, false));
+        }
+        assert_eq!(node.validate_invariants(), Ok(()));
+
+        let state = node.export_state();
+        assert_eq!(state.missings.len(), 1);
+        let mut rounds = state.missings[0]
+            .1
+            .iter()
+            .map(|(_, round)| *round)
+            .collect::<Vec<_>>();
+        rounds.sort();
+        assert_eq!(rounds, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn recent_drops_works() {
+        struct SizedSystem;
+        impl System for SizedSystem {
+            type NodeId = String;
+            type MessageId = u64;
+            type MessagePayload = Vec<u8>;
+        }
+
+        let options = NodeOptions {
+            max_payload_size: Some(0),
+            drop_history: 2,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<SizedSystem>::with_options("foo".to_owned(), options);
+        node.set_payload_size(|p| p.len());
+        node.handle_neighbor_up(&"bar".to_owned());
+
+        let gossip = |sender: &str, id| {
+            let message = Message::new(id, vec![0]);
+            ProtocolMessage::Gossip(GossipMessage::new(&sender.to_owned(), message, 0))
+        };
+        assert!(!node.handle_protocol_message(gossip("qux", 0)));
+        assert!(node.handle_protocol_message(gossip("bar", 1)));
+
+        let drops = node.recent_drops().collect::<Vec<_>>();
+        assert_eq!(drops.len(), 2);
+        assert_eq!(
+            drops[0].reason,
+            DropReason::Rejected(HandleError::UnknownSender)
+        );
+        assert_eq!(drops[0].sender, Some("qux".to_owned()));
+        assert_eq!(drops[0].message_id, Some(0));
+        assert_eq!(drops[1].reason, DropReason::OversizedPayload);
+        assert_eq!(drops[1].sender, Some("bar".to_owned()));
+        assert_eq!(drops[1].message_id, Some(1));
+
+        // Only the most recent drops are retained
+        node.broadcast_message(Message::new(2, vec![0]));
+        let drops = node.recent_drops().collect::<Vec<_>>();
+        assert_eq!(drops.len(), 2);
+        assert_eq!(drops[0].message_id, Some(1));
+        assert_eq!(drops[1].sender, None);
+    }
+
+    #[test]
+    fn rebroadcast_message_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.handle_protocol_message(prune("baz"));
+        node.drain_actions().count();
+
+        node.rebroadcast_message(message(0), 5);
+        assert!(!node.is_originator(&0));
+        assert!(node.messages().contains_key(&0));
+        let mut delivered = 0;
+        let mut sends = Vec::new();
+        while let Some(action) = node.poll_action() {
+            match action {
+                Action::Send {
+                    destination,
+                    message,
+                } => sends.push((destination, message)),
+                Action::Deliver { .. } => delivered += 1,
+                Action::ForgetSuggested { .. } => {}
+            }
+        }
+        assert_eq!(delivered, 1);
+        sends.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(sends.len(), 2);
+        assert!(matches!(&sends[0].1, ProtocolMessage::Gossip(m) if m.round == 6));
+        assert!(matches!(&sends[1].1, ProtocolMessage::Ihave(m) if m.round == 6));
+
+        // A kept message is forwarded again, but not delivered again
+        node.rebroadcast_message(message(0), 5);
+        assert_eq!(node.pending_action_count(), 2);
+        assert_eq!(drain_sends(&mut node).len(), 2);
+    }
+
+    #[test]
+    fn max_graft_round_gap_works() {
+        let options = NodeOptions {
+            max_graft_round_gap: Some(2),
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.handle_neighbor_up(&"qux".to_owned());
+        node.handle_protocol_message(gossip("bar", 0, 1));
+        node.handle_protocol_message(prune("baz"));
+        node.handle_protocol_message(prune("qux"));
+        node.drain_actions().count();
+
+        // A distant GRAFT is served, but the sender stays lazy
+        let graft = GraftMessage::new(&"baz".to_owned(), Some(0), 5);
+        node.handle_protocol_message(graft.into());
+        let sends = drain_sends(&mut node);
+        assert_eq!(sends.len(), 1);
+        assert_eq!(sends[0].0, "baz");
+        assert!(matches!(&sends[0].1, ProtocolMessage::Gossip(m) if m.round == 2));
+        assert!(node.lazy_push_peers().contains("baz"));
+
+        // A GRAFT within the gap promotes the sender
+        let graft = GraftMessage::new(&"qux".to_owned(), Some(0), 4);
+        node.handle_protocol_message(graft.into());
+        assert_eq!(drain_sends(&mut node).len(), 1);
+        assert!(node.eager_push_peers().contains("qux"));
+    }
+
+    #[test]
+    fn merge_peers_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        let set = |ids: &[&str]| {
+            ids.iter()
+                .map(|id| (*id).to_owned())
+                .collect::<HashSet<_>>()
+        };
+        assert!(node.set_peers(set(&["a", "b"]), set(&["c", "d"])));
+
+        assert!(!node.merge_peers(&set(&["foo"]), &set(&[])));
+        assert!(node.merge_peers(&set(&["c", "e"]), &set(&["a", "d", "f"])));
+        assert_eq!(*node.eager_push_peers(), set(&["a", "b", "c", "e"]));
+        assert_eq!(*node.lazy_push_peers(), set(&["d", "f"]));
+    }
+
+    #[test]
+    fn graft_nack_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.handle_neighbor_up(&"qux".to_owned());
+        node.handle_protocol_message(prune("bar"));
+        node.handle_protocol_message(prune("baz"));
+        node.handle_protocol_message(ihave("bar", 0, 1, false));
+        node.handle_protocol_message(ihave("baz", 0, 2, false));
+        node.drain_actions().count();
+
+        node.clock_mut().tick(Duration::from_millis(500));
+        let sends = drain_sends(&mut node);
+        assert_eq!(sends.len(), 1);
+        assert_eq!(sends[0].0, "bar");
+
+        // The peer which does not have the message replies with a GRAFT_NACK
+        let mut bar = Node::<TestSystem>::new("bar".to_owned());
+        bar.handle_neighbor_up(&"foo".to_owned());
+        bar.handle_protocol_message(sends[0].1.clone());
+        let nacks = drain_sends(&mut bar);
+        assert_eq!(nacks.len(), 1);
+        assert!(matches!(&nacks[0].1, ProtocolMessage::GraftNack(m) if m.message_id == 0));
+
+        // The requester immediately requests the message to the next owner
+        node.handle_protocol_message(nacks[0].1.clone());
+        assert_eq!(node.validate_invariants(), Ok(()));
+        let sends = drain_sends(&mut node);
+        assert_eq!(sends.len(), 1);
+        assert_eq!(sends[0].0, "baz");
+        assert!(matches!(&sends[0].1, ProtocolMessage::Graft(m) if m.round == 2));
+
+        // No owners are left
+        let nack = GraftNackMessage::new(&"baz".to_owned(), 0);
+        node.handle_protocol_message(nack.into());
+        assert_eq!(drain_sends(&mut node).len(), 0);
+    }
+
+    #[test]
+    fn unsolicited_graft_nack_is_ignored() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned(), "qux".to_owned()]);
+        node.demote_to_lazy(&"bar".to_owned());
+        node.demote_to_lazy(&"baz".to_owned());
+        node.handle_protocol_message(ihave("bar", 0, 1, false));
+        node.handle_protocol_message(ihave("baz", 0, 2, false));
+        node.drain_actions().count();
+
+        // "qux" has never been grafted for the message
+        let nack = GraftNackMessage::new(&"qux".to_owned(), 0);
+        node.handle_protocol_message(nack.into());
+        assert_eq!(drain_sends(&mut node).len(), 0);
+
+        // The owners are still tried in order
+        node.clock_mut().tick(Duration::from_millis(500));
+        let sends = drain_sends(&mut node);
+        assert_eq!(sends.len(), 1);
+        assert_eq!(sends[0].0, "bar");
+        let nack = GraftNackMessage::new(&"baz".to_owned(), 0);
+        node.handle_protocol_message(nack.into());
+        assert_eq!(drain_sends(&mut node).len(), 0);
+        assert_eq!(node.waiting_messages(), 1);
+
+        // A nack for the outstanding request is counted separately from the timeouts
+        let nack = GraftNackMessage::new(&"bar".to_owned(), 0);
+        node.handle_protocol_message(nack.into());
+        let sends = drain_sends(&mut node);
+        assert_eq!(sends.len(), 1);
+        assert_eq!(sends[0].0, "baz");
+        assert_eq!(
+            node.graft_stats(),
+            GraftStats {
+                sent: 2,
+                satisfied: 0,
+                timed_out: 0,
+                nacked: 1
+            }
+        );
+    }
+
+    #[test]
+    fn per_peer_send_rate_works() {
+        let options = NodeOptions {
+            per_peer_send_rate: Some((1, Duration::from_millis(100))),
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.handle_protocol_message(prune("baz"));
+        for id in 0..3 {
+            node.broadcast_message(message(id));
+        }
+
+        // The burst is spread across the ticks
+        for id in 0..3 {
+            let sends = drain_sends(&mut node);
+            assert_eq!(sends.len(), 2);
+            assert!(sends.iter().any(|(d, m)| d == "bar"
+                && matches!(m, ProtocolMessage::Gossip(m) if m.message.id == id)));
+            assert!(sends
+                .iter()
+                .any(|(d, m)| d == "baz"
+                    && matches!(m, ProtocolMessage::Ihave(m) if m.message_id == id)));
+
+            assert_eq!(drain_sends(&mut node).len(), 0);
+            assert_eq!(node.is_idle(), id == 2);
+            node.clock_mut().tick(Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn originated_count_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.broadcast_message(message(0));
+        node.broadcast_message(message(1));
+        node.handle_protocol_message(gossip("bar", 2, 0));
+        assert_eq!(node.messages().len(), 3);
+        assert_eq!(node.originated_count(), 2);
+
+        node.forget_message(&0);
+        assert_eq!(node.originated_count(), 1);
+    }
+
+    #[test]
+    fn messages_eq_works() {
+        struct BytesSystem;
+        impl System for BytesSystem {
+            type NodeId = String;
+            type MessageId = u64;
+            type MessagePayload = Vec<u8>;
+        }
 
-        // setup neighbors
-        for i in 0..nodes.len() {
-            let neighbors = rand::random::<usize>() % 3 + 1;
-            for _ in 0..neighbors {
-                let j = rand::random::<usize>() % nodes.len();
-                nodes[i].handle_neighbor_up(&j.to_string());
-                nodes[j].handle_neighbor_up(&i.to_string());
+        let mut nodes = ["foo", "bar", "baz", "qux"]
+            .iter()
+            .map(|id| Node::<BytesSystem>::new((*id).to_owned()))
+            .collect::<Vec<_>>();
+        for (a, b) in &[(0, 1), (1, 2), (2, 3), (3, 0)] {
+            let (id_a, id_b) = (nodes[*a].id().clone(), nodes[*b].id().clone());
+            nodes[*a].handle_neighbor_up(&id_b);
+            nodes[*b].handle_neighbor_up(&id_a);
+        }
+        for (i, node) in nodes.iter_mut().enumerate() {
+            node.broadcast_message(Message::new(i as u64, vec![i as u8]));
+        }
+        let mut did_something = true;
+        while did_something {
+            did_something = false;
+            for i in 0..nodes.len() {
+                while let Some(action) = nodes[i].poll_action() {
+                    did_something = true;
+                    if let Action::Send {
+                        destination,
+                        message,
+                    } = action
+                    {
+                        let j = nodes.iter().position(|n| *n.id() == destination).unwrap();
+                        nodes[j].handle_protocol_message(message);
+                    }
+                }
             }
         }
+        assert!(nodes.iter().all(|n| n.messages_eq(&nodes[0])));
 
-        // broadcast messages
-        const MESSAGE_COUNT: usize = 50;
-        for m in 0..MESSAGE_COUNT {
-            let sender = rand::random::<usize>() % nodes.len();
-            nodes[sender].broadcast_message(message(m as u64));
+        nodes[0].forget_message(&0);
+        assert!(!nodes[1].messages_eq(&nodes[0]));
+        nodes[1].forget_message(&0);
+        nodes[1].broadcast_message(Message::new(0, vec![9]));
+        assert!(!nodes[1].messages_eq(&nodes[2]));
+    }
+
+    #[test]
+    fn reciprocal_graft_works() {
+        for enabled in &[false, true] {
+            let options = NodeOptions {
+                reciprocal_graft: *enabled,
+                ..NodeOptions::default()
+            };
+            let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+            node.handle_neighbor_up(&"bar".to_owned());
+            let graft = GraftMessage::new(&"bar".to_owned(), Some(0), 3);
+            node.handle_protocol_message(graft.clone().into());
+            let sends = drain_sends(&mut node);
+            assert!(matches!(&sends[0].1, ProtocolMessage::GraftNack(_)));
+            if *enabled {
+                assert_eq!(sends.len(), 2);
+                assert!(matches!(&sends[1].1, ProtocolMessage::Graft(m)
+                    if m.message_id == Some(0) && m.round == 3));
+            } else {
+                assert_eq!(sends.len(), 1);
+            }
+            assert_eq!(node.validate_invariants(), Ok(()));
+
+            // A further GRAFT is only replied with a GRAFT_NACK
+            node.handle_protocol_message(graft.into());
+            assert_eq!(drain_sends(&mut node).len(), 1);
         }
+    }
 
-        execute(&mut nodes);
-        for node in &nodes {
-            assert_eq!(node.messages().len(), MESSAGE_COUNT);
-            assert_eq!(node.waiting_messages(), 0);
+    #[test]
+    fn set_id_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        assert!(!node.set_id("bar".to_owned()));
+        assert!(node.set_id("baz".to_owned()));
+        assert_eq!(node.id(), "baz");
+
+        node.broadcast_message(message(0));
+        let sends = drain_sends(&mut node);
+        assert_eq!(sends.len(), 1);
+        assert_eq!(sends[0].1.sender(), "baz");
+
+        // Messages carrying the new identifier are treated as own ones
+        assert!(!node.handle_protocol_message(gossip("baz", 1, 0)));
+    }
+
+    #[test]
+    fn dedup_delivers_works() {
+        for enabled in &[false, true] {
+            let options = NodeOptions {
+                dedup_delivers: *enabled,
+                ..NodeOptions::default()
+            };
+            let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+            node.broadcast_message(message(0));
+            node.broadcast_message(message(0));
+            let delivers = node
+                .drain_actions()
+                .filter(|a| matches!(a, Action::Deliver { .. }))
+                .count();
+            assert_eq!(delivers, if *enabled { 1 } else { 2 });
+
+            // A forgotten message can be delivered again
+            node.forget_message(&0);
+            node.broadcast_message(message(0));
+            assert_eq!(node.drain_actions().count(), 1);
+        }
+    }
+
+    #[test]
+    fn insert_message_silent_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.insert_message_silent(message(0));
+        assert!(node.has_message(&0));
+        assert!(!node.has_message(&1));
+        assert_eq!(node.poll_action().map(|_| ()), None);
+
+        let graft = GraftMessage::new(&"bar".to_owned(), Some(0), 1);
+        node.handle_protocol_message(graft.into());
+        let sends = drain_sends(&mut node);
+        assert_eq!(sends.len(), 1);
+        assert!(matches!(&sends[0].1, ProtocolMessage::Gossip(m) if m.message.id == 0));
+    }
+
+    #[test]
+    fn graft_stats_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.handle_neighbor_up(&"qux".to_owned());
+        node.handle_protocol_message(prune("bar"));
+        node.handle_protocol_message(prune("baz"));
+        node.handle_protocol_message(ihave("bar", 0, 1, false));
+        node.handle_protocol_message(ihave("baz", 1, 1, false));
+        node.clock_mut().tick(Duration::from_millis(500));
+        assert_eq!(drain_sends(&mut node).len(), 2);
+        assert_eq!(node.graft_stats().sent, 2);
+
+        node.clock_mut().tick(Duration::from_millis(100));
+        node.handle_protocol_message(gossip("bar", 0, 1));
+        node.clock_mut().tick(Duration::from_millis(400));
+        node.drain_actions().count();
+        assert_eq!(
+            node.graft_stats(),
+            GraftStats {
+                sent: 2,
+                satisfied: 1,
+                timed_out: 1,
+                nacked: 0
+            }
+        );
+    }
+
+    #[test]
+    fn graft_stats_counts_regrafts() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned()]);
+        node.drain_actions().count();
+
+        assert!(node.request_message(&"bar".to_owned(), 0));
+        assert!(node.request_message(&"bar".to_owned(), 0));
+        assert!(node.request_message(&"baz".to_owned(), 0));
+        assert!(node.request_message(&"baz".to_owned(), 1));
+        node.drain_actions().count();
+        assert_eq!(node.graft_stats().sent, 4);
+        assert_eq!(node.graft_stats().timed_out, 1);
+
+        node.handle_protocol_message(gossip("baz", 0, 1));
+        node.clock_mut().tick(Duration::from_millis(1000));
+        node.drain_actions().count();
+        let stats = node.graft_stats();
+        assert_eq!(
+            stats,
+            GraftStats {
+                sent: 4,
+                satisfied: 1,
+                timed_out: 3,
+                nacked: 0
+            }
+        );
+    }
+
+    #[test]
+    fn time_source_works() {
+        use std::sync::atomic::AtomicU64;
+        use time::{NodeTime, TimeSource};
+
+        struct MillisSource(Arc<AtomicU64>);
+        impl TimeSource for MillisSource {
+            fn now(&self) -> NodeTime {
+                NodeTime::from_duration(Duration::from_millis(self.0.load(Ordering::SeqCst)))
+            }
+        }
+
+        let millis = Arc::new(AtomicU64::new(0));
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.set_time_source(MillisSource(Arc::clone(&millis)));
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.handle_protocol_message(prune("bar"));
+        node.handle_protocol_message(ihave("bar", 0, 1, false));
+        assert_eq!(drain_sends(&mut node).len(), 0);
+
+        // The IHAVE timeout fires without ticking the clock
+        millis.store(500, Ordering::SeqCst);
+        let sends = drain_sends(&mut node);
+        assert_eq!(sends.len(), 1);
+        assert!(matches!(&sends[0].1, ProtocolMessage::Graft(m) if m.message_id == Some(0)));
+        assert_eq!(node.clock().now().as_duration(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn messages_by_round_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_protocol_message(gossip("bar", 0, 3));
+        node.handle_protocol_message(gossip("bar", 1, 1));
+        node.handle_protocol_message(gossip("bar", 2, 5));
+        node.handle_protocol_message(gossip("bar", 3, 1));
+        node.broadcast_message(message(4));
+        node.insert_message_silent(message(5));
+
+        assert_eq!(
+            node.messages_by_round(),
+            vec![(&4, 0), (&1, 1), (&3, 1), (&0, 3), (&2, 5)]
+        );
+    }
+
+    #[test]
+    fn peer_flap_cooldown_works() {
+        let options = NodeOptions {
+            new_neighbor_mode: NewNeighborMode::Lazy,
+            peer_flap_cooldown: Duration::from_millis(100),
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        assert!(node.lazy_push_peers().contains("bar"));
+
+        let graft = GraftMessage::new(&"bar".to_owned(), None, 0);
+        node.handle_protocol_message(graft.into());
+        assert!(node.eager_push_peers().contains("bar"));
+
+        // The PRUNE crossing the GRAFT is ignored
+        node.handle_protocol_message(prune("bar"));
+        assert!(node.eager_push_peers().contains("bar"));
+
+        node.clock_mut().tick(Duration::from_millis(100));
+        node.handle_protocol_message(prune("bar"));
+        assert!(node.lazy_push_peers().contains("bar"));
+    }
+
+    #[test]
+    fn inferred_origin_works() {
+        let options = NodeOptions {
+            track_provenance: true,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.broadcast_message(message(0));
+        assert_eq!(node.inferred_origin(&0), Some(&"foo".to_owned()));
+        assert_eq!(node.inferred_origin(&1), None);
+
+        // The message originated by "baz" arrives via "bar" first, and directly from "baz" later
+        node.handle_protocol_message(gossip("bar", 1, 2));
+        assert_eq!(node.inferred_origin(&1), Some(&"bar".to_owned()));
+        node.handle_protocol_message(gossip("baz", 1, 1));
+        assert_eq!(node.inferred_origin(&1), Some(&"baz".to_owned()));
+        assert_eq!(
+            node.message_provenance(&1).map(|p| &p.received_from),
+            Some(&Some("bar".to_owned()))
+        );
+    }
+
+    #[test]
+    fn optimization_disabled_works() {
+        let options = NodeOptions {
+            optimization_enabled: false,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned()]);
+        node.demote_to_lazy(&"baz".to_owned());
+        node.drain_actions().count();
+
+        node.handle_protocol_message(ihave("baz", 0, 0, false));
+        node.handle_protocol_message(gossip("bar", 0, 2));
+        assert_eq!(node.optimization_count(), 0);
+
+        let sends = drain_sends(&mut node);
+        assert!(!sends
+            .iter()
+            .any(|(_, m)| matches!(m, ProtocolMessage::Graft(_) | ProtocolMessage::Prune(_))));
+        assert!(node.eager_push_peers().contains(&"bar".to_owned()));
+        assert!(node.lazy_push_peers().contains(&"baz".to_owned()));
+    }
+
+    #[test]
+    fn apply_event_works() {
+        let trace = vec![
+            NodeEvent::NeighborUp("bar".to_owned()),
+            NodeEvent::Inbound(ihave("bar", 1, 0, false)),
+            NodeEvent::Tick(Duration::from_millis(600)),
+            NodeEvent::Inbound(gossip("bar", 1, 1)),
+            NodeEvent::Forget(1),
+            NodeEvent::Broadcast(message(0)),
+        ];
+
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        let mut log = Vec::new();
+        for event in trace {
+            for action in node.apply_event(event) {
+                log.push(match action {
+                    Action::Send {
+                        destination,
+                        message,
+                    } => match message {
+                        ProtocolMessage::Graft(m) => {
+                            format!("graft {} {:?}", destination, m.message_id)
+                        }
+                        ProtocolMessage::Gossip(m) => {
+                            format!("gossip {} {}", destination, m.message.id)
+                        }
+                        m => format!("send {} {:?}", destination, m),
+                    },
+                    Action::Deliver { message } => format!("deliver {}", message.id),
+                    Action::ForgetSuggested { message_id } => format!("forget {}", message_id),
+                });
+            }
+        }
+        assert_eq!(
+            log,
+            [
+                "graft bar Some(1)",
+                "deliver 1",
+                "deliver 0",
+                "gossip bar 0"
+            ]
+        );
+        assert!(!node.has_message(&1));
+    }
+
+    #[test]
+    fn resync_recent_only_works() {
+        let options = NodeOptions {
+            resync_recent_only: Some(10),
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        for i in 0..100 {
+            node.broadcast_message(message(i));
+        }
+        node.drain_actions().count();
+
+        node.handle_neighbor_up(&"bar".to_owned());
+        let ids = drain_sends(&mut node)
+            .into_iter()
+            .filter_map(|(_, m)| match m {
+                ProtocolMessage::Ihave(m) => Some(m.message_id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, (90..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn is_overloaded_works() {
+        let options = NodeOptions {
+            action_queue_high_water: Some(3),
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+
+        node.broadcast_message(message(0));
+        assert!(!node.is_overloaded());
+        node.broadcast_message(message(1));
+        assert!(node.is_overloaded());
+
+        node.poll_action();
+        assert!(!node.is_overloaded());
+        node.drain_actions().count();
+        assert!(!node.is_overloaded());
+    }
+
+    #[test]
+    fn shuffle_eager_order_works() {
+        let options = NodeOptions {
+            shuffle_eager_order: true,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.set_rng(SplitMix64::new(7));
+        let peers = (0..8).map(|i| format!("peer{}", i)).collect::<Vec<_>>();
+        node.handle_neighbor_up_many(peers.clone());
+        node.drain_actions().count();
+
+        let mut orders = Vec::new();
+        for i in 0..2 {
+            node.broadcast_message(message(i));
+            let order = drain_sends(&mut node)
+                .into_iter()
+                .map(|(d, _)| d)
+                .collect::<Vec<_>>();
+            let reached = order.iter().cloned().collect::<HashSet<_>>();
+            assert_eq!(reached, peers.iter().cloned().collect::<HashSet<_>>());
+            orders.push(order);
+        }
+        assert_ne!(orders[0], orders[1]);
+    }
+
+    #[test]
+    fn tree_policy_works() {
+        struct NeverPrune;
+        impl TreePolicy<TestSystem> for NeverPrune {
+            fn should_prune(&self, _peer: &String, _round: u16) -> bool {
+                false
+            }
+        }
+
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.set_tree_policy(NeverPrune);
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned()]);
+        node.drain_actions().count();
+
+        node.handle_protocol_message(gossip("bar", 0, 0));
+        node.handle_protocol_message(gossip("baz", 0, 1));
+        let sends = drain_sends(&mut node);
+        assert!(!sends
+            .iter()
+            .any(|(_, m)| matches!(m, ProtocolMessage::Prune(_))));
+        assert_eq!(node.eager_count(), 2);
+    }
+
+    #[test]
+    fn forget_all_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned()]);
+        node.demote_to_lazy(&"baz".to_owned());
+        for i in 0..3 {
+            node.broadcast_message(message(i));
+        }
+        node.handle_protocol_message(ihave("baz", 10, 0, false));
+        node.drain_actions().count();
+        assert_eq!(node.waiting_messages(), 1);
+
+        assert_eq!(node.forget_all(), 3);
+        assert!(node.messages().is_empty());
+        assert_eq!(node.waiting_messages(), 0);
+        assert_eq!(node.eager_count(), 1);
+        assert_eq!(node.lazy_count(), 1);
+        assert!(node.validate_invariants().is_ok());
+        assert_eq!(node.forget_all(), 0);
+    }
+
+    #[test]
+    fn verifier_works() {
+        struct RejectBaz;
+        impl Verifier<TestSystem> for RejectBaz {
+            fn verify(&self, message: &ProtocolMessage<TestSystem>) -> bool {
+                message.sender() != "baz"
+            }
+        }
+
+        let options = NodeOptions {
+            drop_history: 1,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.set_verifier(RejectBaz);
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned()]);
+        node.drain_actions().count();
+
+        assert_eq!(
+            node.try_handle_protocol_message(gossip("baz", 0, 0)),
+            Err(HandleError::Unverified)
+        );
+        assert!(!node.handle_protocol_message_ref(&gossip("baz", 1, 0)));
+        assert_eq!(node.unverified_message_count(), 2);
+        assert_eq!(
+            node.recent_drops().next().map(|d| d.reason),
+            Some(DropReason::Rejected(HandleError::Unverified))
+        );
+        assert!(node.messages().is_empty());
+
+        assert!(node.handle_protocol_message(gossip("bar", 0, 0)));
+        assert!(node.has_message(&0));
+        assert_eq!(node.unverified_message_count(), 2);
+    }
+
+    #[test]
+    fn verifier_is_called_once_per_message() {
+        struct CountingVerifier(Arc<AtomicUsize>);
+        impl Verifier<TestSystem> for CountingVerifier {
+            fn verify(&self, _message: &ProtocolMessage<TestSystem>) -> bool {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                true
+            }
+        }
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.set_verifier(CountingVerifier(Arc::clone(&calls)));
+        node.handle_neighbor_up(&"bar".to_owned());
+
+        assert!(node.handle_protocol_message_ref(&gossip("bar", 0, 0)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(node.handle_protocol_message_ref(&gossip("bar", 0, 0)));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(node.handle_protocol_message_ref(&ihave("bar", 1, 0, false)));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert!(node.handle_protocol_message(gossip("bar", 2, 0)));
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn received_at_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+
+        node.clock_mut().tick(Duration::from_millis(10));
+        let t0 = node.clock().now();
+        node.broadcast_message(message(0));
+
+        node.clock_mut().tick(Duration::from_millis(20));
+        let t1 = node.clock().now();
+        node.handle_protocol_message(gossip("bar", 1, 0));
+
+        node.clock_mut().tick(Duration::from_millis(30));
+        node.handle_protocol_message(gossip("bar", 1, 1));
+
+        assert_eq!(node.received_at(&0), Some(t0));
+        assert_eq!(node.received_at(&1), Some(t1));
+        assert_eq!(node.received_at(&2), None);
+
+        node.forget_message(&1);
+        assert_eq!(node.received_at(&1), None);
+    }
+
+    #[test]
+    fn drain_actions_to_works() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned()]);
+        node.drain_actions().count();
+
+        node.broadcast_message(message(0));
+        node.broadcast_message(message(1));
+        assert_eq!(node.pending_action_count(), 6);
+
+        let to_bar = node.drain_actions_to(&"bar".to_owned());
+        let ids = to_bar
+            .iter()
+            .map(|a| match a {
+                Action::Send {
+                    destination,
+                    message: ProtocolMessage::Gossip(m),
+                } if destination == "bar" => m.message.id,
+                _ => panic!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [0, 1]);
+
+        let rest = node.drain_actions().collect::<Vec<_>>();
+        assert_eq!(rest.len(), 4);
+        assert!(rest.iter().all(|a| match a {
+            Action::Send { destination, .. } => destination == "baz",
+            Action::Deliver { .. } => true,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn adaptive_promotion_works() {
+        let options = NodeOptions {
+            adaptive_promotion: true,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        assert!(node.lazy_push_peers().contains(&"bar".to_owned()));
+
+        node.handle_protocol_message(ihave("bar", 0, 0, false));
+        node.clock_mut().tick(Duration::from_millis(1000));
+        let sends = drain_sends(&mut node);
+        assert!(sends
+            .iter()
+            .any(|(d, m)| d == "bar" && matches!(m, ProtocolMessage::Graft(_))));
+        assert!(node.lazy_push_peers().contains(&"bar".to_owned()));
+
+        node.handle_protocol_message(gossip("bar", 0, 1));
+        assert!(node.eager_push_peers().contains(&"bar".to_owned()));
+        assert_eq!(node.graft_stats().satisfied, 1);
+    }
+
+    #[test]
+    fn optimize_ignores_lower_gossip_round() {
+        for &(ihave_round, gossip_round) in &[(3, 1), (u16::MAX, 0), (2, 2)] {
+            let mut node = Node::<TestSystem>::new("foo".to_owned());
+            node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned()]);
+            node.demote_to_lazy(&"baz".to_owned());
+            node.drain_actions().count();
+
+            node.handle_protocol_message(ihave("baz", 0, ihave_round, false));
+            node.handle_protocol_message(gossip("bar", 0, gossip_round));
+            assert_eq!(node.optimization_count(), 0);
+            assert!(!drain_sends(&mut node)
+                .iter()
+                .any(|(_, m)| matches!(m, ProtocolMessage::Graft(_) | ProtocolMessage::Prune(_))));
+        }
+    }
+
+    #[test]
+    fn basic_system_works() {
+        type S = BasicSystem<String, u64, Vec<u8>>;
+        let mut foo = Node::<S>::new("foo".to_owned());
+        let mut bar = Node::<S>::new("bar".to_owned());
+        foo.handle_neighbor_up(&"bar".to_owned());
+        bar.handle_neighbor_up(&"foo".to_owned());
+        foo.drain_actions().count();
+        bar.drain_actions().count();
+
+        foo.broadcast_message(Message {
+            id: 0,
+            payload: vec![1, 2, 3],
+        });
+        let mut delivered = Vec::new();
+        for action in foo.drain_actions() {
+            if let Action::Send { message, .. } = action {
+                bar.handle_protocol_message(message);
+            }
+        }
+        for action in bar.drain_actions() {
+            if let Action::Deliver { message } = action {
+                delivered.push(message.payload);
+            }
+        }
+        assert_eq!(delivered, [vec![1, 2, 3]]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_protocol_message_works() {
+        #[derive(Debug)]
+        struct SerdeSystem;
+        impl System for SerdeSystem {
+            type NodeId = String;
+            type MessageId = u64;
+            type MessagePayload = String;
+        }
+
+        let mut node = Node::<SerdeSystem>::new("foo".to_owned());
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.broadcast_message(Message::new(0, "hello".to_owned()));
+        let message = node
+            .drain_actions()
+            .find_map(|a| match a {
+                Action::Send { message, .. } => Some(message),
+                _ => None,
+            })
+            .unwrap();
+
+        let json = serde_json::to_string(&message).unwrap();
+        let decoded: ProtocolMessage<SerdeSystem> = serde_json::from_str(&json).unwrap();
+        match decoded {
+            ProtocolMessage::Gossip(m) => {
+                assert_eq!(m.sender, "foo");
+                assert_eq!(m.message.id, 0);
+                assert_eq!(m.message.payload, "hello");
+                assert_eq!(m.round, 1);
+            }
+            m => panic!("{:?}", m),
+        }
+
+        let graft = ProtocolMessage::<SerdeSystem>::from(GraftMessage {
+            sender: "bar".to_owned(),
+            message_id: None,
+            round: 3,
+        });
+        let json = serde_json::to_string(&graft).unwrap();
+        match serde_json::from_str(&json).unwrap() {
+            ProtocolMessage::<SerdeSystem>::Graft(m) => {
+                assert_eq!(m.sender, "bar");
+                assert_eq!(m.message_id, None);
+                assert_eq!(m.round, 3);
+            }
+            m => panic!("{:?}", m),
+        }
+    }
+
+    #[test]
+    fn set_ihave_timeout_saturates_rescaled_expiry() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.set_ihave_timeout(Duration::from_nanos(1));
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.drain_actions().count();
+        node.handle_protocol_message(ihave("bar", 0, 0, false));
+
+        node.set_ihave_timeout(Duration::MAX);
+        assert!(node.time_until_next_expiry().unwrap() > Duration::from_secs(u64::MAX / 2));
+        node.clock_mut().tick(Duration::from_secs(1));
+        assert_eq!(drain_sends(&mut node).len(), 0);
+    }
+
+    #[test]
+    fn set_ihave_timeout_from_zero_does_not_rescale() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.set_ihave_timeout(Duration::from_millis(0));
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.drain_actions().count();
+        node.handle_protocol_message(ihave("bar", 0, 0, false));
+
+        node.set_ihave_timeout(Duration::from_millis(100));
+        assert_eq!(
+            node.time_until_next_expiry(),
+            Some(Duration::from_millis(0))
+        );
+        assert_eq!(drain_sends(&mut node).len(), 1);
+    }
+
+    #[test]
+    fn huge_peer_flap_cooldown_never_ends() {
+        let options = NodeOptions {
+            new_neighbor_mode: NewNeighborMode::Lazy,
+            peer_flap_cooldown: Duration::MAX,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.clock_mut().tick(Duration::from_secs(1));
+
+        let graft = GraftMessage::new(&"bar".to_owned(), None, 0);
+        node.handle_protocol_message(graft.into());
+        assert!(node.eager_push_peers().contains("bar"));
+
+        node.clock_mut().tick(Duration::from_secs(1_000_000));
+        node.handle_protocol_message(prune("bar"));
+        assert!(node.eager_push_peers().contains("bar"));
+    }
+
+    #[test]
+    fn zero_max_grafts_per_cycle_is_treated_as_one() {
+        let options = NodeOptions {
+            max_grafts_per_cycle: Some(0),
+            ..NodeOptions::default()
+        };
+        let timeout = options.ihave_timeout;
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        for id in 0..2 {
+            node.handle_protocol_message(ihave("bar", id, 0, false));
+        }
+        node.clock_mut().tick(timeout);
+
+        for &expected in &[1, 1, 0] {
+            let grafts = node
+                .drain_actions()
+                .filter(|a| {
+                    matches!(
+                        a,
+                        Action::Send {
+                            message: ProtocolMessage::Graft(_),
+                            ..
+                        }
+                    )
+                })
+                .count();
+            assert_eq!(grafts, expected);
+        }
+    }
+
+    #[test]
+    fn huge_lazy_suppression_window_holds_ihaves() {
+        let options = NodeOptions {
+            lazy_suppression_window: Duration::MAX,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.handle_neighbor_up(&"baz".to_owned());
+        node.demote_to_lazy(&"baz".to_owned());
+        node.drain_actions().count();
+        node.clock_mut().tick(Duration::from_secs(1));
+
+        node.handle_protocol_message(gossip("bar", 0, 0));
+        node.drain_actions().count();
+        let baz = "baz".to_owned();
+        assert_eq!(node.pending_lazy_announcements(&baz).count(), 1);
+
+        node.clock_mut().tick(Duration::from_secs(1_000_000));
+        assert_eq!(drain_sends(&mut node).len(), 0);
+
+        node.handle_protocol_message(ihave("baz", 0, 0, false));
+        assert_eq!(node.pending_lazy_announcements(&baz).count(), 0);
+    }
+
+    #[test]
+    fn set_per_peer_send_rate_works() {
+        let options = NodeOptions {
+            per_peer_send_rate: Some((1, Duration::MAX)),
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.handle_neighbor_up(&"bar".to_owned());
+        node.clock_mut().tick(Duration::from_secs(1));
+        node.drain_actions().count();
+        for id in 0..3 {
+            node.broadcast_message(message(id));
+        }
+        assert_eq!(drain_sends(&mut node).len(), 1);
+
+        // The window never ends
+        node.clock_mut().tick(Duration::from_secs(1_000_000));
+        assert_eq!(drain_sends(&mut node).len(), 0);
+        assert!(!node.is_idle());
+
+        node.set_per_peer_send_rate(None);
+        assert_eq!(node.options().per_peer_send_rate, None);
+        assert_eq!(node.peek_actions().count(), 2);
+        assert_eq!(drain_sends(&mut node).len(), 2);
+        assert!(node.is_idle());
+    }
+
+    #[test]
+    fn huge_delivery_rate_window_works() {
+        let options = NodeOptions {
+            delivery_rate_window: Duration::MAX,
+            ..NodeOptions::default()
+        };
+        let mut node = Node::<TestSystem>::with_options("foo".to_owned(), options);
+        node.clock_mut().tick(Duration::from_secs(1));
+        node.broadcast_message(message(0));
+        node.clock_mut().tick(Duration::from_secs(1));
+        node.broadcast_message(message(1));
+        assert_eq!(node.recent_delivery_rate(Duration::from_secs(4)), 0.5);
+    }
+
+    #[test]
+    fn shared_clock_sync_handles_invalid_rates() {
+        let shared = time::SharedClock::new();
+        shared.tick(Duration::from_secs(10));
+
+        let mut clock = time::Clock::new();
+        shared.sync(&mut clock, -1.0);
+        shared.sync(&mut clock, f64::NAN);
+        assert_eq!(clock.now().as_duration(), Duration::from_secs(0));
+
+        shared.sync(&mut clock, f64::INFINITY);
+        assert_eq!(clock.now().as_duration(), Duration::MAX);
+
+        let mut clock = time::Clock::new();
+        shared.sync(&mut clock, f64::MAX);
+        assert_eq!(clock.now().as_duration(), Duration::MAX);
+    }
+
+    #[test]
+    fn graft_nack_does_not_delay_remaining_owners() {
+        let mut node = Node::<TestSystem>::new("foo".to_owned());
+        node.handle_neighbor_up_many(vec!["bar".to_owned(), "baz".to_owned(), "qux".to_owned()]);
+        for peer in ["bar", "baz", "qux"] {
+            node.demote_to_lazy(&peer.to_owned());
+        }
+        node.handle_neighbor_up(&"quux".to_owned());
+        for (i, peer) in ["bar", "baz", "qux"].into_iter().enumerate() {
+            node.handle_protocol_message(ihave(peer, 0, i as u16, false));
         }
+        node.drain_actions().count();
+
+        node.clock_mut().tick(Duration::from_millis(500));
+        assert_eq!(drain_sends(&mut node)[0].0, "bar");
+        let nack = GraftNackMessage::new(&"bar".to_owned(), 0);
+        node.handle_protocol_message(nack.into());
+        assert_eq!(drain_sends(&mut node)[0].0, "baz");
+        assert_eq!(node.validate_invariants(), Ok(()));
+
+        // The last owner is tried when the request to "baz" times out
+        node.clock_mut().tick(Duration::from_millis(500));
+        let sends = drain_sends(&mut node);
+        assert_eq!(sends.len(), 1);
+        assert_eq!(sends[0].0, "qux");
+        assert_eq!(node.validate_invariants(), Ok(()));
+
+        node.clock_mut().tick(Duration::from_millis(1000));
+        assert_eq!(drain_sends(&mut node).len(), 0);
+        assert_eq!(node.validate_invariants(), Ok(()));
+        assert_eq!(node.waiting_messages(), 0);
     }
 
     fn message(id: u64) -> Message<TestSystem> {
         Message { id, payload: () }
     }
 
+    fn gossip(sender: &str, id: u64, round: u16) -> ProtocolMessage<TestSystem> {
+        GossipMessage::new(&sender.to_owned(), message(id), round).into()
+    }
+
+    fn ihave(sender: &str, id: u64, round: u16, realtime: bool) -> ProtocolMessage<TestSystem> {
+        IhaveMessage::new(&sender.to_owned(), id, round, realtime).into()
+    }
+
+    fn prune(sender: &str) -> ProtocolMessage<TestSystem> {
+        PruneMessage::new(&sender.to_owned()).into()
+    }
+
+    fn drain_sends(node: &mut Node<TestSystem>) -> Vec<(String, ProtocolMessage<TestSystem>)> {
+        let mut sends = Vec::new();
+        while let Some(action) = node.poll_action() {
+            if let Action::Send {
+                destination,
+                message,
+            } = action
+            {
+                sends.push((destination, message));
+            }
+        }
+        sends
+    }
+
     fn execute_single(node: &mut Node<TestSystem>) -> Vec<Message<TestSystem>> {
         let mut delivered = Vec::new();
         while let Some(action) = node.poll_action() {
@@ -127,7 +3207,7 @@ mod tests {
                 Action::Deliver { message } => {
                     delivered.push(message);
                 }
-                Action::Send { .. } => panic!("{:?}", action),
+                Action::Send { .. } | Action::ForgetSuggested { .. } => panic!("{:?}", action),
             }
         }
         delivered
@@ -137,21 +3217,30 @@ mod tests {
         nodes.iter_mut().find(|n| n.id() == id).unwrap()
     }
 
-    fn execute(nodes: &mut [Node<TestSystem>]) {
+    /// Messages received by each node in the order of reception.
+    type Trace = HashMap<String, Vec<String>>;
+
+    fn execute(nodes: &mut [Node<TestSystem>]) -> Trace {
+        let mut trace = Trace::new();
         let mut did_something = true;
         while did_something {
             did_something = false;
 
             let mut i = 0;
             while i < nodes.len() {
+                assert_eq!(nodes[i].validate_invariants(), Ok(()));
                 while let Some(action) = nodes[i].poll_action() {
                     did_something = true;
                     match action {
-                        Action::Deliver { .. } => {}
+                        Action::Deliver { .. } | Action::ForgetSuggested { .. } => {}
                         Action::Send {
                             destination,
                             message,
                         } => {
+                            trace
+                                .entry(destination.clone())
+                                .or_default()
+                                .push(format!("{:?}", message));
                             get(nodes, &destination).handle_protocol_message(message);
                         }
                     }
@@ -162,5 +3251,6 @@ mod tests {
                 node.clock_mut().tick(Duration::from_millis(100));
             }
         }
+        trace
     }
 }
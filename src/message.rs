@@ -1,8 +1,18 @@
 //! Application and protocol messages.
 use crate::System;
 use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
 
 /// Application message.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T::MessageId: serde::Serialize, T::MessagePayload: serde::Serialize",
+        deserialize = "T::MessageId: serde::Deserialize<'de>, T::MessagePayload: serde::Deserialize<'de>"
+    ))
+)]
 pub struct Message<T: System> {
     /// The identifier of the message.
     pub id: T::MessageId,
@@ -55,15 +65,94 @@ where
 {
 }
 
+/// A reference-counted message payload.
+///
+/// [`System::MessagePayload`] is required to implement `Clone`,
+/// and the payload is cloned each time a message is delivered or forwarded.
+/// Wrapping a payload with this type makes the clones cheap
+/// and allows for using payload types which do not implement `Clone`.
+///
+/// [`System::MessagePayload`]: ../trait.System.html#associatedtype.MessagePayload
+///
+/// # Examples
+///
+/// ```
+/// use plumtree::message::ArcPayload;
+///
+/// struct NonClone(usize);
+///
+/// let a = ArcPayload::new(NonClone(10));
+/// let b = a.clone();
+/// assert_eq!(b.0, 10);
+/// assert!(ArcPayload::ptr_eq(&a, &b));
+/// ```
+pub struct ArcPayload<P>(Arc<P>);
+impl<P> ArcPayload<P> {
+    /// Makes a new `ArcPayload` instance.
+    pub fn new(payload: P) -> Self {
+        ArcPayload(Arc::new(payload))
+    }
+
+    /// Returns `true` if the two payloads point to the same allocation.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Arc::ptr_eq(&this.0, &other.0)
+    }
+}
+impl<P> Clone for ArcPayload<P> {
+    fn clone(&self) -> Self {
+        ArcPayload(Arc::clone(&self.0))
+    }
+}
+impl<P> Deref for ArcPayload<P> {
+    type Target = P;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl<P> From<P> for ArcPayload<P> {
+    fn from(f: P) -> Self {
+        ArcPayload::new(f)
+    }
+}
+impl<P> From<Arc<P>> for ArcPayload<P> {
+    fn from(f: Arc<P>) -> Self {
+        ArcPayload(f)
+    }
+}
+impl<P: fmt::Debug> fmt::Debug for ArcPayload<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ArcPayload({:?})", self.0)
+    }
+}
+impl<P: PartialEq> PartialEq for ArcPayload<P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq(&other.0)
+    }
+}
+impl<P: Eq> Eq for ArcPayload<P> {}
+
 /// Messages defined by the Plumtree algorithm.
 ///
 /// Those are used for inter-node communications.
 #[allow(missing_docs)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T::NodeId: serde::Serialize, T::MessageId: serde::Serialize, T::MessagePayload: serde::Serialize",
+        deserialize = "T::NodeId: serde::Deserialize<'de>, T::MessageId: serde::Deserialize<'de>, T::MessagePayload: serde::Deserialize<'de>"
+    ))
+)]
 pub enum ProtocolMessage<T: System> {
     Gossip(GossipMessage<T>),
     Ihave(IhaveMessage<T>),
+    IhaveMany(IhaveManyMessage<T>),
     Graft(GraftMessage<T>),
     Prune(PruneMessage<T>),
+    GraftNack(GraftNackMessage<T>),
+    Probe(ProbeMessage<T>),
+    ProbeAck(ProbeAckMessage<T>),
 }
 impl<T: System> ProtocolMessage<T> {
     /// Returns the sender of the message.
@@ -71,8 +160,40 @@ impl<T: System> ProtocolMessage<T> {
         match self {
             ProtocolMessage::Gossip(m) => &m.sender,
             ProtocolMessage::Ihave(m) => &m.sender,
+            ProtocolMessage::IhaveMany(m) => &m.sender,
             ProtocolMessage::Graft(m) => &m.sender,
             ProtocolMessage::Prune(m) => &m.sender,
+            ProtocolMessage::GraftNack(m) => &m.sender,
+            ProtocolMessage::Probe(m) => &m.sender,
+            ProtocolMessage::ProbeAck(m) => &m.sender,
+        }
+    }
+
+    /// Returns the identifier of the application message associated with the message.
+    ///
+    /// This method returns `None` for the messages that are not associated with
+    /// a single application message (i.e., `IhaveMany`, `Prune`, `Probe` and `ProbeAck`)
+    /// and `Graft` messages without identifiers.
+    pub fn message_id(&self) -> Option<&T::MessageId> {
+        match self {
+            ProtocolMessage::Gossip(m) => Some(&m.message.id),
+            ProtocolMessage::Ihave(m) => Some(&m.message_id),
+            ProtocolMessage::Graft(m) => m.message_id.as_ref(),
+            ProtocolMessage::GraftNack(m) => Some(&m.message_id),
+            _ => None,
+        }
+    }
+
+    /// Returns the round (i.e., hop count) carried by the message.
+    ///
+    /// This method returns `None` for the messages that do not carry a single round
+    /// (i.e., `IhaveMany`, `Prune`, `GraftNack`, `Probe` and `ProbeAck`).
+    pub fn round(&self) -> Option<u16> {
+        match self {
+            ProtocolMessage::Gossip(m) => Some(m.round),
+            ProtocolMessage::Ihave(m) => Some(m.round),
+            ProtocolMessage::Graft(m) => Some(m.round),
+            _ => None,
         }
     }
 }
@@ -81,8 +202,12 @@ impl<T: System> Clone for ProtocolMessage<T> {
         match self {
             ProtocolMessage::Gossip(m) => m.clone().into(),
             ProtocolMessage::Ihave(m) => m.clone().into(),
+            ProtocolMessage::IhaveMany(m) => m.clone().into(),
             ProtocolMessage::Graft(m) => m.clone().into(),
             ProtocolMessage::Prune(m) => m.clone().into(),
+            ProtocolMessage::GraftNack(m) => m.clone().into(),
+            ProtocolMessage::Probe(m) => m.clone().into(),
+            ProtocolMessage::ProbeAck(m) => m.clone().into(),
         }
     }
 }
@@ -96,8 +221,12 @@ where
         match self {
             ProtocolMessage::Gossip(m) => write!(f, "Gossip({:?})", m),
             ProtocolMessage::Ihave(m) => write!(f, "Ihave({:?})", m),
+            ProtocolMessage::IhaveMany(m) => write!(f, "IhaveMany({:?})", m),
             ProtocolMessage::Graft(m) => write!(f, "Graft({:?})", m),
             ProtocolMessage::Prune(m) => write!(f, "Prune({:?})", m),
+            ProtocolMessage::GraftNack(m) => write!(f, "GraftNack({:?})", m),
+            ProtocolMessage::Probe(m) => write!(f, "Probe({:?})", m),
+            ProtocolMessage::ProbeAck(m) => write!(f, "ProbeAck({:?})", m),
         }
     }
 }
@@ -111,6 +240,11 @@ impl<T: System> From<IhaveMessage<T>> for ProtocolMessage<T> {
         ProtocolMessage::Ihave(f)
     }
 }
+impl<T: System> From<IhaveManyMessage<T>> for ProtocolMessage<T> {
+    fn from(f: IhaveManyMessage<T>) -> Self {
+        ProtocolMessage::IhaveMany(f)
+    }
+}
 impl<T: System> From<GraftMessage<T>> for ProtocolMessage<T> {
     fn from(f: GraftMessage<T>) -> Self {
         ProtocolMessage::Graft(f)
@@ -121,8 +255,31 @@ impl<T: System> From<PruneMessage<T>> for ProtocolMessage<T> {
         ProtocolMessage::Prune(f)
     }
 }
+impl<T: System> From<GraftNackMessage<T>> for ProtocolMessage<T> {
+    fn from(f: GraftNackMessage<T>) -> Self {
+        ProtocolMessage::GraftNack(f)
+    }
+}
+impl<T: System> From<ProbeMessage<T>> for ProtocolMessage<T> {
+    fn from(f: ProbeMessage<T>) -> Self {
+        ProtocolMessage::Probe(f)
+    }
+}
+impl<T: System> From<ProbeAckMessage<T>> for ProtocolMessage<T> {
+    fn from(f: ProbeAckMessage<T>) -> Self {
+        ProtocolMessage::ProbeAck(f)
+    }
+}
 
 /// `GOSSIP` message.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T::NodeId: serde::Serialize, T::MessageId: serde::Serialize, T::MessagePayload: serde::Serialize",
+        deserialize = "T::NodeId: serde::Deserialize<'de>, T::MessageId: serde::Deserialize<'de>, T::MessagePayload: serde::Deserialize<'de>"
+    ))
+)]
 pub struct GossipMessage<T: System> {
     /// The sender of the message.
     pub sender: T::NodeId,
@@ -167,6 +324,14 @@ where
 }
 
 /// `IHAVE` message.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T::NodeId: serde::Serialize, T::MessageId: serde::Serialize",
+        deserialize = "T::NodeId: serde::Deserialize<'de>, T::MessageId: serde::Deserialize<'de>"
+    ))
+)]
 pub struct IhaveMessage<T: System> {
     /// The sender of the message.
     pub sender: T::NodeId,
@@ -221,7 +386,86 @@ where
     }
 }
 
+/// Aggregated `IHAVE` message.
+///
+/// This announces multiple messages at once.
+/// Each entry is handled in the same way as an individual `IhaveMessage`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T::NodeId: serde::Serialize, T::MessageId: serde::Serialize",
+        deserialize = "T::NodeId: serde::Deserialize<'de>, T::MessageId: serde::Deserialize<'de>"
+    ))
+)]
+pub struct IhaveManyMessage<T: System> {
+    /// The sender of the message.
+    pub sender: T::NodeId,
+
+    /// The identifiers and hop counts of the messages that the sender has keeping.
+    pub entries: Vec<(T::MessageId, u16)>,
+
+    /// Indicates whether this is a real-time message or a buffered message.
+    ///
+    /// See [`IhaveMessage::realtime`] for details.
+    ///
+    /// [`IhaveMessage::realtime`]: ./struct.IhaveMessage.html#structfield.realtime
+    pub realtime: bool,
+}
+impl<T: System> IhaveManyMessage<T> {
+    /// Makes a new `IhaveManyMessage` instance.
+    pub fn new(sender: &T::NodeId, entries: Vec<(T::MessageId, u16)>, realtime: bool) -> Self {
+        IhaveManyMessage {
+            sender: sender.clone(),
+            entries,
+            realtime,
+        }
+    }
+
+    /// Splits the message into individual `IhaveMessage`s.
+    pub fn into_ihaves(self) -> impl Iterator<Item = IhaveMessage<T>> {
+        let IhaveManyMessage {
+            sender,
+            entries,
+            realtime,
+        } = self;
+        entries
+            .into_iter()
+            .map(move |(message_id, round)| IhaveMessage::new(&sender, message_id, round, realtime))
+    }
+}
+impl<T: System> Clone for IhaveManyMessage<T> {
+    fn clone(&self) -> Self {
+        IhaveManyMessage {
+            sender: self.sender.clone(),
+            entries: self.entries.clone(),
+            realtime: self.realtime,
+        }
+    }
+}
+impl<T: System> fmt::Debug for IhaveManyMessage<T>
+where
+    T::NodeId: fmt::Debug,
+    T::MessageId: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "IhaveManyMessage {{ sender: {:?}, entries: {:?}, realtime: {:?} }}",
+            self.sender, self.entries, self.realtime
+        )
+    }
+}
+
 /// `GRAFT` message.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T::NodeId: serde::Serialize, T::MessageId: serde::Serialize",
+        deserialize = "T::NodeId: serde::Deserialize<'de>, T::MessageId: serde::Deserialize<'de>"
+    ))
+)]
 pub struct GraftMessage<T: System> {
     /// The sender of the message.
     pub sender: T::NodeId,
@@ -265,6 +509,14 @@ where
 }
 
 /// `PRUNE` message.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T::NodeId: serde::Serialize",
+        deserialize = "T::NodeId: serde::Deserialize<'de>"
+    ))
+)]
 pub struct PruneMessage<T: System> {
     /// The sender of the message.
     pub sender: T::NodeId,
@@ -291,3 +543,147 @@ where
         write!(f, "PruneMessage {{ sender: {:?} }}", self.sender)
     }
 }
+
+/// `GRAFT_NACK` message.
+///
+/// This is sent in reply to a `GraftMessage` requesting a message that the sender does not have,
+/// so that the requester can request the message to another owner without waiting for the timeout.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T::NodeId: serde::Serialize, T::MessageId: serde::Serialize",
+        deserialize = "T::NodeId: serde::Deserialize<'de>, T::MessageId: serde::Deserialize<'de>"
+    ))
+)]
+pub struct GraftNackMessage<T: System> {
+    /// The sender of the message.
+    pub sender: T::NodeId,
+
+    /// The identifier of the requested message.
+    pub message_id: T::MessageId,
+}
+impl<T: System> GraftNackMessage<T> {
+    pub(crate) fn new(sender: &T::NodeId, message_id: T::MessageId) -> Self {
+        GraftNackMessage {
+            sender: sender.clone(),
+            message_id,
+        }
+    }
+}
+impl<T: System> Clone for GraftNackMessage<T> {
+    fn clone(&self) -> Self {
+        GraftNackMessage {
+            sender: self.sender.clone(),
+            message_id: self.message_id.clone(),
+        }
+    }
+}
+impl<T: System> fmt::Debug for GraftNackMessage<T>
+where
+    T::NodeId: fmt::Debug,
+    T::MessageId: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "GraftNackMessage {{ sender: {:?}, message_id: {:?} }}",
+            self.sender, self.message_id
+        )
+    }
+}
+
+/// `PROBE` message.
+///
+/// This is not a part of the Plumtree algorithm and does not affect the spanning tree.
+/// The receiver of the message replies with a `ProbeAckMessage` that has the same nonce.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T::NodeId: serde::Serialize",
+        deserialize = "T::NodeId: serde::Deserialize<'de>"
+    ))
+)]
+pub struct ProbeMessage<T: System> {
+    /// The sender of the message.
+    pub sender: T::NodeId,
+
+    /// The nonce used for associating the message with the reply.
+    pub nonce: u64,
+}
+impl<T: System> ProbeMessage<T> {
+    pub(crate) fn new(sender: &T::NodeId, nonce: u64) -> Self {
+        ProbeMessage {
+            sender: sender.clone(),
+            nonce,
+        }
+    }
+}
+impl<T: System> Clone for ProbeMessage<T> {
+    fn clone(&self) -> Self {
+        ProbeMessage {
+            sender: self.sender.clone(),
+            nonce: self.nonce,
+        }
+    }
+}
+impl<T: System> fmt::Debug for ProbeMessage<T>
+where
+    T::NodeId: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ProbeMessage {{ sender: {:?}, nonce: {:?} }}",
+            self.sender, self.nonce
+        )
+    }
+}
+
+/// `PROBE_ACK` message.
+///
+/// This is the reply for a `ProbeMessage`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T::NodeId: serde::Serialize",
+        deserialize = "T::NodeId: serde::Deserialize<'de>"
+    ))
+)]
+pub struct ProbeAckMessage<T: System> {
+    /// The sender of the message.
+    pub sender: T::NodeId,
+
+    /// The nonce of the associated `ProbeMessage`.
+    pub nonce: u64,
+}
+impl<T: System> ProbeAckMessage<T> {
+    pub(crate) fn new(sender: &T::NodeId, nonce: u64) -> Self {
+        ProbeAckMessage {
+            sender: sender.clone(),
+            nonce,
+        }
+    }
+}
+impl<T: System> Clone for ProbeAckMessage<T> {
+    fn clone(&self) -> Self {
+        ProbeAckMessage {
+            sender: self.sender.clone(),
+            nonce: self.nonce,
+        }
+    }
+}
+impl<T: System> fmt::Debug for ProbeAckMessage<T>
+where
+    T::NodeId: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ProbeAckMessage {{ sender: {:?}, nonce: {:?} }}",
+            self.sender, self.nonce
+        )
+    }
+}
@@ -4,12 +4,14 @@ use crate::System;
 use std::cmp::Ordering;
 use std::collections::{BinaryHeap, HashMap};
 use std::fmt;
+use std::mem;
 use std::time::Duration;
 
 pub struct MissingMessages<T: System> {
     timeout_queue: BinaryHeap<QueueItem<T>>,
     ihaves: HashMap<T::MessageId, IhaveEntry<T::NodeId>>,
     entry_seqno: u64,
+    item_seqno: u64,
 }
 impl<T: System> fmt::Debug for MissingMessages<T>
 where
@@ -19,8 +21,9 @@ where
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "MissingMessages {{ timeout_queue: {:?}, ihaves: {:?}, entry_seqno: {:?} }}",
-            self.timeout_queue, self.ihaves, self.entry_seqno
+            "MissingMessages {{ timeout_queue: {:?}, ihaves: {:?}, entry_seqno: {:?}, \
+             item_seqno: {:?} }}",
+            self.timeout_queue, self.ihaves, self.entry_seqno, self.item_seqno
         )
     }
 }
@@ -30,47 +33,71 @@ impl<T: System> MissingMessages<T> {
             timeout_queue: BinaryHeap::new(),
             ihaves: HashMap::new(),
             entry_seqno: 0,
+            item_seqno: 0,
         }
     }
 
-    pub fn push(&mut self, ihave: IhaveMessage<T>, clock: &Clock, timeout: Duration) {
+    pub fn push(
+        &mut self,
+        ihave: IhaveMessage<T>,
+        clock: &Clock,
+        timeout: Duration,
+        realtime_timeout: Duration,
+        max_owners: usize,
+    ) {
         let seqno = self.entry_seqno;
         let entry = self
             .ihaves
             .entry(ihave.message_id.clone())
             .or_insert_with(|| {
                 let mut expiry_time = clock.now();
-                if !ihave.realtime {
+                if ihave.realtime {
+                    expiry_time += realtime_timeout;
+                } else {
                     expiry_time += timeout;
                 }
                 IhaveEntry {
                     seqno,
                     head_round: ihave.round,
                     head_owner: ihave.sender.clone(),
-                    owners: 0,
+                    owners: Vec::new(),
                     next_expiry_time: expiry_time,
+                    first_received_time: clock.now(),
+                    cancelled_timeouts: 0,
                 }
             });
+        if entry.owners.len() >= max_owners.max(1) {
+            // Keeps the owners nearest to the source of the message
+            let i = (0..entry.owners.len())
+                .max_by_key(|&i| entry.owners[i].1)
+                .expect("never fails");
+            if ihave.round < entry.owners[i].1 {
+                entry.owners[i] = (ihave.sender, ihave.round);
+            }
+            return;
+        }
 
         let expiry_time = entry.next_expiry_time;
         entry.next_expiry_time += timeout;
-        entry.owners += 1;
-        if entry.owners == 1 {
+        entry.owners.push((ihave.sender.clone(), ihave.round));
+        if entry.owners.len() == 1 {
             self.entry_seqno += 1;
         }
 
         self.timeout_queue.push(QueueItem::Message {
             expiry_time,
+            item_seqno: self.item_seqno,
             ihave,
             entry_seqno: entry.seqno,
         });
+        self.item_seqno += 1;
     }
 
     pub fn pop_expired(&mut self, clock: &Clock) -> Option<IhaveMessage<T>> {
         let is_expired = |x: &QueueItem<_>| x.expiry_time() <= clock.now();
-        while self.timeout_queue.peek().map_or(false, is_expired) {
+        while self.timeout_queue.peek().is_some_and(is_expired) {
             let item = self.timeout_queue.pop().expect("never fails");
-            match self.ihaves.get(item.message_id()) {
+            match self.ihaves.get_mut(item.message_id()) {
                 None => {
                     // (a) The entry has been removed due to reception of the associated GOSSIP message
                     continue;
@@ -79,28 +106,26 @@ impl<T: System> MissingMessages<T> {
                     // (b) Like `(a)`, but the message has been forgot before receiving new IHAVE messages
                     continue;
                 }
+                Some(entry)
+                    if entry.owners.is_empty() && matches!(item, QueueItem::Message { .. }) =>
+                {
+                    // (c) The last timeouts of the message have been cancelled by `pop_owner`
+                    debug_assert!(entry.cancelled_timeouts > 0);
+                    entry.cancelled_timeouts -= 1;
+                    continue;
+                }
                 _ => {}
             }
 
             match item {
                 QueueItem::Message { ihave, .. } => {
-                    let entry = self.ihaves.get_mut(&ihave.message_id).expect("never fails");
-                    assert_ne!(entry.owners, 0);
-
-                    entry.owners -= 1;
-                    entry.head_round = ihave.round;
-                    entry.head_owner = ihave.sender.clone();
-                    if entry.owners == 0 {
-                        self.timeout_queue.push(QueueItem::Entry {
-                            expiry_time: entry.next_expiry_time,
-                            entry_seqno: entry.seqno,
-                            message_id: ihave.message_id.clone(),
-                        });
-                    }
-                    return Some(ihave);
+                    return Some(self.take_owner(ihave));
                 }
                 QueueItem::Entry { message_id, .. } => {
-                    let expired = self.ihaves.get(&message_id).map(|e| e.owners) == Some(0);
+                    let expired = self
+                        .ihaves
+                        .get(&message_id)
+                        .is_some_and(|e| e.owners.is_empty());
                     if expired {
                         self.ihaves.remove(&message_id);
                     }
@@ -110,18 +135,167 @@ impl<T: System> MissingMessages<T> {
         None
     }
 
+    pub fn pop_owner(&mut self, message_id: &T::MessageId) -> Option<IhaveMessage<T>> {
+        let entry = self.ihaves.get_mut(message_id)?;
+        if entry.owners.is_empty() {
+            return None;
+        }
+
+        // Cancels the last scheduled timeout of the message to keep the number of the timeouts
+        // consistent with the number of the owners.
+        // Instead of being removed from the queue, it is skipped by `pop_expired`
+        // when no owners are left.
+        entry.cancelled_timeouts += 1;
+        let ihave = IhaveMessage::new(
+            &entry.head_owner,
+            message_id.clone(),
+            entry.head_round,
+            false,
+        );
+        Some(self.take_owner(ihave))
+    }
+
     pub fn remove(&mut self, message_id: &T::MessageId) {
         self.ihaves.remove(message_id);
     }
 
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T::MessageId) -> bool,
+    {
+        self.ihaves.retain(|id, _| f(id));
+    }
+
+    pub fn rescale_timeouts(&mut self, clock: &Clock, ratio: f64) {
+        let now = clock.now();
+        let rescale = |t: &mut NodeTime| {
+            if *t > now {
+                let remaining = t.as_duration() - now.as_duration();
+                let scaled = Duration::try_from_secs_f64(remaining.as_secs_f64() * ratio)
+                    .unwrap_or(Duration::MAX);
+                *t = now
+                    .checked_add(scaled)
+                    .unwrap_or_else(|| NodeTime::from_duration(Duration::MAX));
+            }
+        };
+        let mut items = std::mem::take(&mut self.timeout_queue).into_vec();
+        for item in &mut items {
+            match item {
+                QueueItem::Message { expiry_time, .. } | QueueItem::Entry { expiry_time, .. } => {
+                    rescale(expiry_time)
+                }
+            }
+        }
+        self.timeout_queue = BinaryHeap::from(items);
+        for entry in self.ihaves.values_mut() {
+            rescale(&mut entry.next_expiry_time);
+        }
+    }
+
+    pub fn contains(&self, message_id: &T::MessageId) -> bool {
+        self.ihaves.contains_key(message_id)
+    }
+
     pub fn waiting_messages(&self) -> usize {
         self.ihaves.len()
     }
 
+    pub fn timeout_queue_len(&self) -> usize {
+        self.timeout_queue.len()
+    }
+
+    pub fn stuck_messages<F>(&self, clock: &Clock, threshold: Duration, f: F) -> Vec<T::MessageId>
+    where
+        F: Fn(&T::NodeId) -> bool,
+    {
+        let now = clock.now().as_duration();
+        self.ihaves
+            .iter()
+            .filter(|(_, e)| now.saturating_sub(e.first_received_time.as_duration()) >= threshold)
+            .filter(|(_, e)| !e.owners.iter().any(|x| f(&x.0)))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    pub fn approx_memory_bytes(&self) -> usize {
+        let owners = self.ihaves.values().map(|e| e.owners.len()).sum::<usize>();
+        self.timeout_queue.len() * mem::size_of::<QueueItem<T>>()
+            + self.ihaves.len()
+                * (mem::size_of::<T::MessageId>() + mem::size_of::<IhaveEntry<T::NodeId>>())
+            + owners * mem::size_of::<(T::NodeId, u16)>()
+    }
+
     pub fn next_expiry_time(&self) -> Option<NodeTime> {
         self.timeout_queue.peek().map(|x| x.expiry_time())
     }
 
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn validate(&self) -> Result<(), String>
+    where
+        T::MessageId: fmt::Debug,
+    {
+        for (message_id, entry) in &self.ihaves {
+            let items = self
+                .timeout_queue
+                .iter()
+                .filter(|x| x.entry_seqno() == entry.seqno && x.message_id() == message_id);
+            if entry.owners.is_empty() {
+                if !items.clone().any(|x| matches!(x, QueueItem::Entry { .. })) {
+                    return Err(format!(
+                        "The entry of the missing message {:?} has no owners and is never expired",
+                        message_id
+                    ));
+                }
+            } else {
+                let count = items
+                    .filter(|x| matches!(x, QueueItem::Message { .. }))
+                    .count();
+                if count != entry.owners.len() + entry.cancelled_timeouts {
+                    return Err(format!(
+                        "The missing message {:?} has {} owners but {} queued IHAVE messages",
+                        message_id,
+                        entry.owners.len(),
+                        count
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn owners(&self) -> impl Iterator<Item = (&T::MessageId, &[(T::NodeId, u16)])> {
+        self.ihaves
+            .iter()
+            .filter(|(_, e)| !e.owners.is_empty())
+            .map(|(id, e)| (id, &e.owners[..]))
+    }
+
+    fn take_owner(&mut self, mut ihave: IhaveMessage<T>) -> IhaveMessage<T> {
+        let entry = self.ihaves.get_mut(&ihave.message_id).expect("never fails");
+        assert!(!entry.owners.is_empty());
+
+        // Selects the owner nearest to the source of the message
+        let i = (0..entry.owners.len())
+            .min_by_key(|&i| entry.owners[i].1)
+            .expect("never fails");
+        let (owner, round) = entry.owners.remove(i);
+        ihave.sender = owner;
+        ihave.round = round;
+
+        entry.head_round = ihave.round;
+        entry.head_owner = ihave.sender.clone();
+        if entry.owners.is_empty() {
+            self.timeout_queue.push(QueueItem::Entry {
+                expiry_time: entry.next_expiry_time,
+                item_seqno: self.item_seqno,
+                entry_seqno: entry.seqno,
+                message_id: ihave.message_id.clone(),
+            });
+            self.item_seqno += 1;
+        }
+        ihave
+    }
+
     pub fn get_ihave(&self, message_id: &T::MessageId) -> Option<(u16, &T::NodeId)> {
         self.ihaves
             .get(message_id)
@@ -134,18 +308,22 @@ struct IhaveEntry<N> {
     seqno: u64,
     head_round: u16,
     head_owner: N,
-    owners: usize,
+    owners: Vec<(N, u16)>,
     next_expiry_time: NodeTime,
+    first_received_time: NodeTime,
+    cancelled_timeouts: usize,
 }
 
 enum QueueItem<T: System> {
     Message {
         expiry_time: NodeTime,
+        item_seqno: u64,
         entry_seqno: u64,
         ihave: IhaveMessage<T>,
     },
     Entry {
         expiry_time: NodeTime,
+        item_seqno: u64,
         entry_seqno: u64,
         message_id: T::MessageId,
     },
@@ -159,6 +337,14 @@ impl<T: System> QueueItem<T> {
         }
     }
 
+    fn item_seqno(&self) -> u64 {
+        match self {
+            QueueItem::Message { item_seqno, .. } | QueueItem::Entry { item_seqno, .. } => {
+                *item_seqno
+            }
+        }
+    }
+
     fn entry_seqno(&self) -> u64 {
         match self {
             QueueItem::Message { entry_seqno, .. } | QueueItem::Entry { entry_seqno, .. } => {
@@ -176,7 +362,7 @@ impl<T: System> QueueItem<T> {
 }
 impl<T: System> PartialEq for QueueItem<T> {
     fn eq(&self, other: &Self) -> bool {
-        self.expiry_time() == other.expiry_time()
+        self.cmp(other) == Ordering::Equal
     }
 }
 impl<T: System> Eq for QueueItem<T> {}
@@ -187,7 +373,8 @@ impl<T: System> PartialOrd for QueueItem<T> {
 }
 impl<T: System> Ord for QueueItem<T> {
     fn cmp(&self, other: &Self) -> Ordering {
-        other.expiry_time().cmp(&self.expiry_time())
+        // Items having the same expiry time are popped in the order they were pushed
+        (other.expiry_time(), other.item_seqno()).cmp(&(self.expiry_time(), self.item_seqno()))
     }
 }
 impl<T: System> fmt::Debug for QueueItem<T>
@@ -1,18 +1,33 @@
-use crate::action::{Action, ActionQueue};
+use crate::action::{Action, ActionQueue, DrainActions};
+use crate::delayed::DelayedIhaves;
+use crate::graft::{GraftDeadlines, OutstandingGrafts};
 use crate::message::{
-    GossipMessage, GraftMessage, IhaveMessage, Message, ProtocolMessage, PruneMessage,
+    GossipMessage, GraftMessage, GraftNackMessage, IhaveMessage, Message, ProbeAckMessage,
+    ProbeMessage, ProtocolMessage, PruneMessage,
 };
 use crate::missing::MissingMessages;
-use crate::time::{Clock, NodeTime};
-use crate::System;
-use std::collections::{HashMap, HashSet};
+use crate::recent::RecentIds;
+use crate::rng::{RngSource, SplitMix64};
+use crate::time::{Clock, NodeTime, TimeSource};
+use crate::{
+    DefaultTreePolicy, IdentityTransform, NodeState, PayloadTransform, System, TreePolicy, Verifier,
+};
+use std::collections::{vec_deque, HashMap, HashSet, VecDeque};
 use std::fmt;
+use std::num::NonZeroU16;
 use std::time::Duration;
 
 /// Options for Plumtree [Node].
 ///
+/// This type is `Clone`, `Debug` and (with the `serde` feature) serializable, so it holds only plain values.
+/// Stateful components such as the random number generator and the time source are installed on the node instead
+/// (see [`Node::set_rng`] and [`Node::set_time_source`]).
+///
 /// [Node]: ./struct.Node.html
+/// [`Node::set_rng`]: ./struct.Node.html#method.set_rng
+/// [`Node::set_time_source`]: ./struct.Node.html#method.set_time_source
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NodeOptions {
     /// Timeout duration of a `IhaveMessage`.
     ///
@@ -26,138 +41,1603 @@ pub struct NodeOptions {
     /// The default value is `Duration::from_millis(500)`.
     pub ihave_timeout: Duration,
 
+    /// Timeout duration of a real-time `IhaveMessage`.
+    ///
+    /// This is applied instead of `ihave_timeout` when a node receives
+    /// the first real-time `IhaveMessage` of a message.
+    /// A non-zero value gives the associated `GossipMessage` a chance to arrive
+    /// before the node sends `GraftMessage`.
+    ///
+    /// The default value is `Duration::from_millis(0)`.
+    pub realtime_ihave_timeout: Duration,
+
     /// Optimization threshold.
     ///
-    /// See "3.8. Optimization" of the [paper] for the description of the parameter.
+    /// See [OptimizationThreshold] for the description of the parameter.
+    ///
+    /// [OptimizationThreshold]: ./struct.OptimizationThreshold.html
+    pub optimization_threshold: OptimizationThreshold,
+
+    /// The minimum number of eager push peers.
+    ///
+    /// If demoting an eager push peer to a lazy push peer would make the number of
+    /// eager push peers less than this value, the node refuses it
+    /// (i.e., received `PruneMessage`s are ignored and no `PruneMessage`s are sent).
+    ///
+    /// Note that this does not affect [`Node::demote_to_lazy`].
+    ///
+    /// The default value is `0`.
+    ///
+    /// [`Node::demote_to_lazy`]: ./struct.Node.html#method.demote_to_lazy
+    pub min_eager_peers: usize,
+
+    /// The push mode initially assigned to new neighbors.
+    ///
+    /// Regardless of the mode, `IhaveMessage`s for the messages kept by the node are
+    /// sent to new neighbors.
+    ///
+    /// The default value is `NewNeighborMode::Eager`.
+    pub new_neighbor_mode: NewNeighborMode,
+
+    /// If `true`, new neighbors are added to the lazy push peers regardless of [`new_neighbor_mode`]
+    /// and are promoted to the eager push peers only when they actually serve a `GossipMessage`
+    /// (e.g., in response to a `GraftMessage`).
+    ///
+    /// Sending a `GraftMessage` does not promote the destination in this mode.
+    /// This reduces the churn of the eager push peers when a node joins a dense overlay.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`new_neighbor_mode`]: #structfield.new_neighbor_mode
+    pub adaptive_promotion: bool,
+
+    /// Whether to track the provenance of each message.
+    ///
+    /// If `true`, the node records the peer from which each message was received and
+    /// the peers to which the message was forwarded.
+    /// The records are removed when the associated messages are forgotten.
+    ///
+    /// The default value is `false`.
+    pub track_provenance: bool,
+
+    /// The maximum number of `GraftMessage`s sent for expired `IhaveMessage`s per [`Node::poll_action`] call.
+    ///
+    /// The remaining expired `IhaveMessage`s are handled by subsequent calls.
+    /// If `None`, the number is unlimited. `Some(0)` is treated as `Some(1)`,
+    /// because otherwise no `GraftMessage` would ever be sent.
+    ///
+    /// The default value is `None`.
+    ///
+    /// [`Node::poll_action`]: ./struct.Node.html#method.poll_action
+    pub max_grafts_per_cycle: Option<usize>,
+
+    /// Whether to record the changes of the eager and lazy push peers.
+    ///
+    /// If `true`, the recorded changes can be retrieved by [`Node::take_peer_changes`].
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`Node::take_peer_changes`]: ./struct.Node.html#method.take_peer_changes
+    pub track_peer_changes: bool,
+
+    /// The delay before sending real-time `IhaveMessage`s to lazy push peers.
+    ///
+    /// If the node observes that a lazy push peer already has a message within this window
+    /// (i.e., it receives a `GossipMessage` or `IhaveMessage` of the message from the peer),
+    /// the `IhaveMessage` for the peer is suppressed.
+    /// If the end of the window overflows `NodeTime`, the `IhaveMessage` is held until suppressed or forgotten.
+    ///
+    /// The default value is `Duration::from_millis(0)` (i.e., no delay and no suppression).
+    pub lazy_suppression_window: Duration,
+
+    /// The maximum number of outstanding `GraftMessage`s for a message.
+    ///
+    /// A `GraftMessage` is outstanding until the associated `GossipMessage` is received,
+    /// `ihave_timeout` elapses or the destination node is removed from the neighbors.
+    /// While the limit is reached, expired `IhaveMessage`s of the message from other owners
+    /// are deferred instead of sending `GraftMessage`s to them.
+    ///
+    /// If `None`, the number is unlimited.
+    ///
+    /// The default value is `None`.
+    pub max_graft_owners_per_message: Option<usize>,
+
+    /// The maximum number of messages that can be waited by the node (see [`Node::waiting_messages`]).
+    ///
+    /// If the limit is reached, `IhaveMessage`s for the messages not being waited are dropped
+    /// (the number of drops can be got by [`Node::dropped_ihave_count`]).
+    /// `IhaveMessage`s for the messages already being waited are still accepted.
+    ///
+    /// Note that dropped `IhaveMessage`s may cause missed messages under sustained overload.
+    ///
+    /// If `None`, the number is unlimited.
+    ///
+    /// The default value is `None`.
+    ///
+    /// [`Node::waiting_messages`]: ./struct.Node.html#method.waiting_messages
+    /// [`Node::dropped_ihave_count`]: ./struct.Node.html#method.dropped_ihave_count
+    pub max_waiting_messages: Option<usize>,
+
+    /// The number of the most recently forgotten messages remembered by the node.
+    ///
+    /// If a `GossipMessage` for a message forgotten by [`Node::forget_message`] is received
+    /// (e.g., a delayed duplicate packet), it is handled as a duplicate
+    /// instead of being delivered again as long as the identifier of the message is remembered.
+    /// Likewise, `IhaveMessage`s for such messages are ignored.
+    ///
+    /// The default value is `0` (i.e., forgotten messages are not remembered).
+    ///
+    /// [`Node::forget_message`]: ./struct.Node.html#method.forget_message
+    pub forgotten_message_memory: usize,
+
+    /// The maximum number of the identifiers of retracted messages remembered by the node.
+    ///
+    /// `GraftMessage`s for the remembered messages are replied with `PruneMessage`s
+    /// (see [`Node::retract_message`]).
+    /// When the limit is exceeded, the oldest identifier is forgotten.
+    ///
+    /// The default value is `1024`.
+    ///
+    /// [`Node::retract_message`]: ./struct.Node.html#method.retract_message
+    pub retracted_message_memory: usize,
+
+    /// The time window during which the deliveries are recorded for [`Node::recent_delivery_rate`].
+    ///
+    /// The default value is `Duration::from_millis(0)` (i.e., the deliveries are not recorded).
+    ///
+    /// [`Node::recent_delivery_rate`]: ./struct.Node.html#method.recent_delivery_rate
+    pub delivery_rate_window: Duration,
+
+    /// The number of duplicate `GossipMessage`s tolerated from an eager push peer before pruning it.
+    ///
+    /// The count of a peer is reset when the peer sends a `GossipMessage` for a new message.
+    /// Larger values keep more eager push links (i.e., faster but more redundant diffusion).
+    ///
+    /// The default value is `0` (i.e., the sender of a duplicate message is pruned immediately).
+    pub duplicate_tolerance: u16,
+
+    /// The maximum size of the payload of a message accepted by the node.
+    ///
+    /// The size of a payload is estimated by the function set by [`Node::set_payload_size`].
+    /// Messages with larger payloads are neither delivered, stored nor forwarded
+    /// (the number of such messages can be got by [`Node::rejected_payload_count`]).
+    ///
+    /// Note that a node rejecting a message does not propagate it,
+    /// so the nodes behind the node may not receive the message.
+    ///
+    /// If `None`, the size is unlimited.
+    ///
+    /// The default value is `None`.
+    ///
+    /// [`Node::set_payload_size`]: ./struct.Node.html#method.set_payload_size
+    /// [`Node::rejected_payload_count`]: ./struct.Node.html#method.rejected_payload_count
+    pub max_payload_size: Option<usize>,
+
+    /// The maximum number of lazy push peers to which an `IhaveMessage` is sent for each message.
+    ///
+    /// If the node has more lazy push peers than this value,
+    /// the destinations are chosen randomly by using the random number generator of the node
+    /// (see [`Node::set_rng`]).
+    /// This reduces the bandwidth consumed by `IhaveMessage`s at the expense of the recovery speed.
+    ///
+    /// If `None`, `IhaveMessage`s are sent to all the lazy push peers.
+    ///
+    /// The default value is `None`.
+    ///
+    /// [`Node::set_rng`]: ./struct.Node.html#method.set_rng
+    pub lazy_fanout: Option<usize>,
+
+    /// If `true`, the order of sending `GossipMessage`s to the eager push peers is shuffled for each message
+    /// by using the random number generator of the node (see [`Node::set_rng`]).
+    ///
+    /// Otherwise, the order is fixed by the iteration order of the peer set,
+    /// which may systematically favor some peers as the first receivers.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`Node::set_rng`]: ./struct.Node.html#method.set_rng
+    pub shuffle_eager_order: bool,
+
+    /// If `true`, the node issues an `Action::ForgetSuggested` for a message
+    /// when all the neighbors seem to have the message.
+    ///
+    /// A neighbor is considered to have a message if the node has received a `GossipMessage`
+    /// or an `IhaveMessage` for the message from the neighbor,
+    /// or the node has sent a `GossipMessage` for the message to the neighbor.
+    /// Note that this is a heuristic (e.g., a sent message may be lost and
+    /// new neighbors may request the message after the suggestion),
+    /// so forgetting the suggested messages does not guarantee that no one requests them.
+    ///
+    /// The default value is `false`.
+    pub suggest_forget: bool,
+
+    /// If `true`, the node counts the delivered messages for each round (see [`Node::round_histogram`]).
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`Node::round_histogram`]: ./struct.Node.html#method.round_histogram
+    pub track_round_histogram: bool,
+
+    /// If `true`, the node never uses eager push gossip.
+    ///
+    /// All the neighbors of the node are kept in the lazy push peers,
+    /// so the node announces messages (including the ones broadcast by the node) only via `IhaveMessage`s
+    /// and receives messages via `GraftMessage`s.
+    /// This is useful for leaf nodes which should not carry the forwarding load (e.g., mobile clients).
+    ///
+    /// The default value is `false`.
+    pub pull_only: bool,
+
+    /// The expected number of messages kept by the node at the same time.
+    ///
+    /// This is used as the initial capacity of the internal message table
+    /// to avoid rehashing during bursts of broadcasts (e.g., at startup).
+    ///
+    /// The default value is `0`.
+    pub expected_messages: usize,
+
+    /// The expected number of neighbors of the node.
+    ///
+    /// This is used as the initial capacity of the eager and lazy push peer sets.
+    ///
+    /// The default value is `0`.
+    pub expected_peers: usize,
+
+    /// The number of the most recently removed neighbors remembered by the node.
+    ///
+    /// A message from a remembered peer is rejected with [`HandleError::RemovedSender`]
+    /// rather than [`HandleError::UnknownSender`] by [`Node::try_handle_protocol_message`].
+    /// This makes it possible to distinguish messages from never known nodes (e.g., spoofed ones)
+    /// from the ones that were in flight when the neighbor was removed.
+    ///
+    /// The default value is `0` (i.e., removed neighbors are not remembered).
+    ///
+    /// [`HandleError::RemovedSender`]: ./enum.HandleError.html#variant.RemovedSender
+    /// [`HandleError::UnknownSender`]: ./enum.HandleError.html#variant.UnknownSender
+    /// [`Node::try_handle_protocol_message`]: ./struct.Node.html#method.try_handle_protocol_message
+    pub removed_peer_memory: usize,
+
+    /// The order of the `IhaveMessage`s sent to a new neighbor for the messages kept by the node.
+    ///
+    /// The default value is `ResyncOrder::OldestFirst`.
+    pub resync_order: ResyncOrder,
+
+    /// The maximum number of messages announced to a new neighbor by `IhaveMessage`s.
+    ///
+    /// If `Some(n)`, only the `n` most recently stored messages are announced
+    /// and the rest are left to be recovered by other means (e.g., anti-entropy).
+    ///
+    /// The default value is `None` (i.e., unlimited).
+    pub resync_recent_only: Option<usize>,
+
+    /// The maximum number of owners retained for a waiting message.
+    ///
+    /// The owners are the peers which sent `IhaveMessage`s of the message and
+    /// have not been requested the message by `GraftMessage`s yet.
+    /// Once the limit is reached, a further `IhaveMessage` replaces the retained owner with the highest round
+    /// if its round is lower than that, and is discarded otherwise.
+    /// This bounds the number of the queued timeouts of a message advertised by many peers.
+    ///
+    /// If `None`, the number is unlimited. `Some(0)` is treated as `Some(1)`.
+    ///
+    /// The default value is `None`.
+    pub max_backup_owners: Option<usize>,
+
+    /// The number of the most recently dropped messages recorded by the node.
+    ///
+    /// See [`Node::recent_drops`] for the details.
+    ///
+    /// The default value is `0` (i.e., dropped messages are not recorded).
+    ///
+    /// [`Node::recent_drops`]: ./struct.Node.html#method.recent_drops
+    pub drop_history: usize,
+
+    /// The maximum gap between the round of a `GraftMessage` and the round of the requested message
+    /// for promoting the sender to an eager push peer.
+    ///
+    /// If the round of a `GraftMessage` is greater than the round at which the node would forward the message
+    /// plus this value, the node sends the message to the sender but does not make it an eager push peer.
+    /// This avoids creating eager push edges that take a long way round from the source.
+    ///
+    /// If `None`, the sender of a `GraftMessage` is always promoted.
+    ///
+    /// The default value is `None`.
+    pub max_graft_round_gap: Option<u16>,
+
+    /// The maximum number of protocol messages sent to a peer per time window.
+    ///
+    /// If `Some((n, window))`, at most `n` messages destined for a peer are queued as `Send` actions
+    /// within `window` of the node local time.
+    /// The excess messages are buffered in the node and released in the following windows,
+    /// as the clock of the node advances and [`Node::poll_action`] is called.
+    /// Note that the window is measured at the time of the last [`Node::poll_action`] call.
+    /// If the end of a window overflows `NodeTime`, the window never ends.
+    ///
+    /// If `None`, the sends are not throttled.
+    ///
+    /// The default value is `None`.
+    ///
+    /// Updating this option via [`Node::options_mut`] takes effect at the next [`Node::poll_action`] call
+    /// (use [`Node::set_per_peer_send_rate`] to apply it immediately).
+    ///
+    /// [`Node::poll_action`]: ./struct.Node.html#method.poll_action
+    /// [`Node::options_mut`]: ./struct.Node.html#method.options_mut
+    /// [`Node::set_per_peer_send_rate`]: ./struct.Node.html#method.set_per_peer_send_rate
+    pub per_peer_send_rate: Option<(usize, Duration)>,
+
+    /// If `true`, the node requests a message to the sender of a `GraftMessage`
+    /// when the requested message has never been seen by the node.
+    ///
+    /// Such a `GraftMessage` suggests that the sender knows the message which the node lacks,
+    /// so the node replies with its own `GraftMessage` (in addition to the `GraftNackMessage`) and
+    /// waits for the message as if an `IhaveMessage` had been received from the sender.
+    ///
+    /// The default value is `false`.
+    pub reciprocal_graft: bool,
+
+    /// If `true`, the node does not issue a `Deliver` action for a message which is kept by the node.
+    ///
+    /// Normally, it happens only when the same message is broadcast more than once.
+    /// Once a message is forgotten, it can be delivered again.
+    /// This protects applications which do not handle the deliveries idempotently.
+    ///
+    /// The default value is `false`.
+    pub dedup_delivers: bool,
+
+    /// The period during which a peer moved between the eager and lazy push peers is not moved again
+    /// by incoming `GraftMessage`s and `PruneMessage`s.
+    ///
+    /// This damps the flapping of the peers caused by `GraftMessage`s and `PruneMessage`s crossing on the wire.
+    /// Note that a `GraftMessage` received during the period is still served.
+    /// If the end of the period overflows `NodeTime`, the period never ends.
+    ///
+    /// The default value is `Duration::from_millis(0)` (i.e., no damping).
+    pub peer_flap_cooldown: Duration,
+
+    /// The high-water mark of the number of queued actions.
+    ///
+    /// While more actions than this are queued, [`Node::is_overloaded`] returns `true`.
+    /// This is purely observational and does not throttle the node.
+    ///
+    /// The default value is `None` (i.e., the node is never considered overloaded).
+    ///
+    /// [`Node::is_overloaded`]: ./struct.Node.html#method.is_overloaded
+    pub action_queue_high_water: Option<usize>,
+
+    /// Whether to run the optimization phase.
+    ///
+    /// If `false`, the node never sends `GraftMessage`s and `PruneMessage`s for optimizing the spanning tree,
+    /// regardless of [`optimization_threshold`].
     ///
-    /// The default value is `2`.
+    /// The default value is `true`.
     ///
-    /// [paper]: http://www.gsd.inesc-id.pt/~ler/reports/srds07.pdf
-    pub optimization_threshold: u16,
+    /// [`optimization_threshold`]: #structfield.optimization_threshold
+    pub optimization_enabled: bool,
+
+    /// The order of the `Deliver` action relative to the `Send` actions for forwarding the message.
+    ///
+    /// The default value is `DeliveryOrder::BeforeForward`.
+    pub delivery_order: DeliveryOrder,
+
+    /// Whether to run the optimization phase in the dry-run mode.
+    ///
+    /// If `true`, the node only counts the optimization decisions
+    /// (see [`Node::optimization_count`]) and does not send `GraftMessage`s and `PruneMessage`s for them.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`Node::optimization_count`]: ./struct.Node.html#method.optimization_count
+    pub optimization_dry_run: bool,
 }
 impl Default for NodeOptions {
     fn default() -> Self {
         NodeOptions {
             ihave_timeout: Duration::from_millis(500),
-            optimization_threshold: 2,
+            realtime_ihave_timeout: Duration::from_millis(0),
+            optimization_threshold: OptimizationThreshold::default(),
+            min_eager_peers: 0,
+            new_neighbor_mode: NewNeighborMode::Eager,
+            adaptive_promotion: false,
+            track_provenance: false,
+            max_grafts_per_cycle: None,
+            track_peer_changes: false,
+            lazy_suppression_window: Duration::from_millis(0),
+            max_graft_owners_per_message: None,
+            max_waiting_messages: None,
+            forgotten_message_memory: 0,
+            retracted_message_memory: 1024,
+            delivery_rate_window: Duration::from_millis(0),
+            duplicate_tolerance: 0,
+            max_payload_size: None,
+            lazy_fanout: None,
+            shuffle_eager_order: false,
+            suggest_forget: false,
+            track_round_histogram: false,
+            pull_only: false,
+            expected_messages: 0,
+            expected_peers: 0,
+            removed_peer_memory: 0,
+            resync_order: ResyncOrder::OldestFirst,
+            resync_recent_only: None,
+            max_backup_owners: None,
+            drop_history: 0,
+            max_graft_round_gap: None,
+            per_peer_send_rate: None,
+            reciprocal_graft: false,
+            dedup_delivers: false,
+            peer_flap_cooldown: Duration::from_millis(0),
+            action_queue_high_water: None,
+            optimization_enabled: true,
+            delivery_order: DeliveryOrder::BeforeForward,
+            optimization_dry_run: false,
         }
     }
 }
 
-/// Plumtree node.
+/// The order of the `IhaveMessage`s sent to a new neighbor by [`Node::handle_neighbor_up`].
 ///
-/// # User's responsibility
+/// The age of a message is determined by the time when the node stored the message.
 ///
-/// For running a node correctly, you have to call the following methods appropriately:
+/// [`Node::handle_neighbor_up`]: ./struct.Node.html#method.handle_neighbor_up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ResyncOrder {
+    /// The messages are announced in the order in which they were stored.
+    OldestFirst,
+
+    /// The messages are announced in the reverse order in which they were stored.
+    ///
+    /// This allows the new neighbor to recover the recent messages first.
+    NewestFirst,
+}
+
+/// Statistics of the `GraftMessage`s sent by Plumtree [Node] for requesting messages.
 ///
-/// - [`poll_action`]
-/// - [`forget_message`]
-/// - [`handle_protocol_message`]
-/// - [`handle_neighbor_up`]
-/// - [`handle_neighbor_down`]
-/// - [`clock_mut`]
+/// A `GraftMessage` is satisfied if the requested message is received within `ihave_timeout`
+/// (see [`NodeOptions::ihave_timeout`]), and times out otherwise.
+/// A `GraftMessage` answered by a `GraftNackMessage` is counted as nacked instead.
+/// If multiple `GraftMessage`s are sent for the same message, at most one of them is satisfied
+/// and the others are counted as timed out, so `sent` eventually equals `satisfied + timed_out + nacked`.
+/// A high ratio of the timed out requests suggests that the timeout is too short.
 ///
-/// For details, refer to the document of each method.
+/// [Node]: ./struct.Node.html
+/// [`NodeOptions::ihave_timeout`]: ./struct.NodeOptions.html#structfield.ihave_timeout
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GraftStats {
+    /// The number of the sent `GraftMessage`s.
+    pub sent: u64,
+
+    /// The number of the `GraftMessage`s whose requested messages have been received in time.
+    pub satisfied: u64,
+
+    /// The number of the `GraftMessage`s whose requested messages have not been received in time.
+    pub timed_out: u64,
+
+    /// The number of the `GraftMessage`s answered by `GraftNackMessage`s.
+    pub nacked: u64,
+}
+
+/// The reason why an incoming message was rejected by [`Node::try_handle_protocol_message`].
 ///
-/// [`poll_action`]: ./struct.Node.html#method.poll_action
-/// [`forget_message`]: ./struct.Node.html#method.forget_message
-/// [`handle_protocol_message`]: ./struct.Node.html#method.handle_protocol_message
-/// [`handle_neighbor_up`]: ./struct.Node.html#method.handle_neighbor_up
-/// [`handle_neighbor_down`]: ./struct.Node.html#method.handle_neighbor_down
-/// [`clock_mut`]: ./struct.Node.html#method.clock_mut
-pub struct Node<T: System> {
-    id: T::NodeId,
-    options: NodeOptions,
-    eager_push_peers: HashSet<T::NodeId>,
-    lazy_push_peers: HashSet<T::NodeId>,
-    messages: HashMap<T::MessageId, T::MessagePayload>,
-    missings: MissingMessages<T>,
-    actions: ActionQueue<T>,
-    clock: Clock,
+/// [`Node::try_handle_protocol_message`]: ./struct.Node.html#method.try_handle_protocol_message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HandleError {
+    /// The sender of the message is the node itself.
+    OwnMessage,
+
+    /// The sender of the message has never been known by the node
+    /// (or has been removed long ago; see [`NodeOptions::removed_peer_memory`]).
+    ///
+    /// [`NodeOptions::removed_peer_memory`]: ./struct.NodeOptions.html#structfield.removed_peer_memory
+    UnknownSender,
+
+    /// The sender of the message is a neighbor which has been recently removed from the node.
+    RemovedSender,
+
+    /// The message was rejected by the verifier of the node (see [`Node::set_verifier`]).
+    ///
+    /// [`Node::set_verifier`]: ./struct.Node.html#method.set_verifier
+    Unverified,
 }
-impl<T: System> fmt::Debug for Node<T>
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandleError::OwnMessage => write!(f, "Message sent by the node itself"),
+            HandleError::UnknownSender => write!(f, "Message sent by an unknown node"),
+            HandleError::RemovedSender => write!(f, "Message sent by a removed neighbor"),
+            HandleError::Unverified => write!(f, "Message failed verification"),
+        }
+    }
+}
+impl std::error::Error for HandleError {}
+
+/// The reason why a message was dropped by Plumtree [Node].
+///
+/// [Node]: ./struct.Node.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DropReason {
+    /// An incoming message was rejected due to its sender or verification.
+    Rejected(HandleError),
+
+    /// The payload of the message exceeds [`NodeOptions::max_payload_size`].
+    ///
+    /// [`NodeOptions::max_payload_size`]: ./struct.NodeOptions.html#structfield.max_payload_size
+    OversizedPayload,
+
+    /// An `IhaveMessage` was discarded due to [`NodeOptions::max_waiting_messages`].
+    ///
+    /// [`NodeOptions::max_waiting_messages`]: ./struct.NodeOptions.html#structfield.max_waiting_messages
+    TooManyWaitingMessages,
+}
+
+/// A record of a message dropped by Plumtree [Node].
+///
+/// See [`Node::recent_drops`] for the details.
+///
+/// [Node]: ./struct.Node.html
+/// [`Node::recent_drops`]: ./struct.Node.html#method.recent_drops
+pub struct DropEvent<T: System> {
+    /// The reason why the message was dropped.
+    pub reason: DropReason,
+
+    /// The sender of the message.
+    ///
+    /// This is `None` if the message was broadcast by the node itself.
+    pub sender: Option<T::NodeId>,
+
+    /// The identifier of the message.
+    ///
+    /// This is `None` if the dropped message does not refer to a specific message (e.g., `PruneMessage`).
+    pub message_id: Option<T::MessageId>,
+}
+impl<T: System> Clone for DropEvent<T> {
+    fn clone(&self) -> Self {
+        DropEvent {
+            reason: self.reason,
+            sender: self.sender.clone(),
+            message_id: self.message_id.clone(),
+        }
+    }
+}
+impl<T: System> fmt::Debug for DropEvent<T>
 where
     T::NodeId: fmt::Debug,
     T::MessageId: fmt::Debug,
-    T::MessagePayload: fmt::Debug,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Node {{ id: {:?}, options: {:?}, eager_push_peers: {:?}, lazy_push_peers: {:?}, \
-             messages: {:?}, missings: {:?}, actions: {:?}, clock: {:?} }}",
-            self.id,
-            self.options,
-            self.eager_push_peers,
-            self.lazy_push_peers,
-            self.messages,
-            self.missings,
-            self.actions,
-            self.clock
+            "DropEvent {{ reason: {:?}, sender: {:?}, message_id: {:?} }}",
+            self.reason, self.sender, self.message_id
         )
     }
 }
-impl<T: System> Node<T> {
-    /// Makes a new `Node` instance.
-    pub fn new(node_id: T::NodeId) -> Self {
-        Self::with_options(node_id, NodeOptions::default())
-    }
-
-    /// Makes a new `Node` instance with the given options.
-    pub fn with_options(node_id: T::NodeId, options: NodeOptions) -> Self {
-        Node {
-            id: node_id,
-            options,
-            eager_push_peers: HashSet::new(),
-            lazy_push_peers: HashSet::new(),
-            messages: HashMap::new(),
-            missings: MissingMessages::new(),
-            actions: ActionQueue::new(),
-            clock: Clock::new(),
-        }
-    }
 
-    /// Returns the identifier of the node.
-    pub fn id(&self) -> &T::NodeId {
-        &self.id
-    }
+/// The order of delivering and forwarding a message in Plumtree [Node].
+///
+/// [Node]: ./struct.Node.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DeliveryOrder {
+    /// The message is delivered to the application before it is forwarded to other nodes.
+    BeforeForward,
 
-    /// Returns the options of the node.
-    pub fn options(&self) -> &NodeOptions {
-        &self.options
-    }
+    /// The message is delivered to the application after it is forwarded to other nodes.
+    ///
+    /// This may shorten the propagation latency of the message.
+    AfterForward,
+}
 
-    /// Returns a mutable reference to the options of the node.
-    pub fn options_mut(&mut self) -> &mut NodeOptions {
-        &mut self.options
-    }
+/// The push mode initially assigned to new neighbors of Plumtree [Node].
+///
+/// [Node]: ./struct.Node.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NewNeighborMode {
+    /// New neighbors are added to the eager push peers.
+    Eager,
 
-    /// Returns the peers with which the node uses eager push gossip for diffusing application messages.
-    pub fn eager_push_peers(&self) -> &HashSet<T::NodeId> {
-        &self.eager_push_peers
-    }
+    /// New neighbors are added to the lazy push peers.
+    ///
+    /// They will be promoted to the eager push peers by `GraftMessage`s if needed.
+    Lazy,
+}
 
-    /// Returns the peers with which the node uses lazy push gossip for diffusing application messages.
-    pub fn lazy_push_peers(&self) -> &HashSet<T::NodeId> {
-        &self.lazy_push_peers
+/// A change of the eager or lazy push peers of Plumtree [Node].
+///
+/// [Node]: ./struct.Node.html
+#[allow(missing_docs)]
+pub enum PeerChange<T: System> {
+    EagerAdded(T::NodeId),
+    EagerRemoved(T::NodeId),
+    LazyAdded(T::NodeId),
+    LazyRemoved(T::NodeId),
+}
+impl<T: System> Clone for PeerChange<T> {
+    fn clone(&self) -> Self {
+        match self {
+            PeerChange::EagerAdded(p) => PeerChange::EagerAdded(p.clone()),
+            PeerChange::EagerRemoved(p) => PeerChange::EagerRemoved(p.clone()),
+            PeerChange::LazyAdded(p) => PeerChange::LazyAdded(p.clone()),
+            PeerChange::LazyRemoved(p) => PeerChange::LazyRemoved(p.clone()),
+        }
+    }
+}
+impl<T: System> fmt::Debug for PeerChange<T>
+where
+    T::NodeId: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PeerChange::EagerAdded(p) => write!(f, "EagerAdded({:?})", p),
+            PeerChange::EagerRemoved(p) => write!(f, "EagerRemoved({:?})", p),
+            PeerChange::LazyAdded(p) => write!(f, "LazyAdded({:?})", p),
+            PeerChange::LazyRemoved(p) => write!(f, "LazyRemoved({:?})", p),
+        }
+    }
+}
+impl<T: System> PartialEq for PeerChange<T> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PeerChange::EagerAdded(a), PeerChange::EagerAdded(b))
+            | (PeerChange::EagerRemoved(a), PeerChange::EagerRemoved(b))
+            | (PeerChange::LazyAdded(a), PeerChange::LazyAdded(b))
+            | (PeerChange::LazyRemoved(a), PeerChange::LazyRemoved(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+impl<T: System> Eq for PeerChange<T> {}
+
+/// An input event of Plumtree [Node].
+///
+/// A sequence of events applied by [`Node::apply_event`] can be used as a replayable trace
+/// for reproducing the behavior of a node deterministically.
+///
+/// [Node]: ./struct.Node.html
+/// [`Node::apply_event`]: ./struct.Node.html#method.apply_event
+pub enum NodeEvent<T: System> {
+    /// An incoming protocol message (see [`Node::handle_protocol_message`]).
+    ///
+    /// [`Node::handle_protocol_message`]: ./struct.Node.html#method.handle_protocol_message
+    Inbound(ProtocolMessage<T>),
+
+    /// A neighbor has come up (see [`Node::handle_neighbor_up`]).
+    ///
+    /// [`Node::handle_neighbor_up`]: ./struct.Node.html#method.handle_neighbor_up
+    NeighborUp(T::NodeId),
+
+    /// A neighbor has gone down (see [`Node::handle_neighbor_down`]).
+    ///
+    /// [`Node::handle_neighbor_down`]: ./struct.Node.html#method.handle_neighbor_down
+    NeighborDown(T::NodeId),
+
+    /// A message broadcast by the node (see [`Node::broadcast_message`]).
+    ///
+    /// [`Node::broadcast_message`]: ./struct.Node.html#method.broadcast_message
+    Broadcast(Message<T>),
+
+    /// The clock of the node proceeds by the given duration (see [`Clock::tick`]).
+    ///
+    /// [`Clock::tick`]: ./time/struct.Clock.html#method.tick
+    Tick(Duration),
+
+    /// A message forgotten by the node (see [`Node::forget_message`]).
+    ///
+    /// [`Node::forget_message`]: ./struct.Node.html#method.forget_message
+    Forget(T::MessageId),
+}
+impl<T: System> Clone for NodeEvent<T> {
+    fn clone(&self) -> Self {
+        match self {
+            NodeEvent::Inbound(m) => NodeEvent::Inbound(m.clone()),
+            NodeEvent::NeighborUp(p) => NodeEvent::NeighborUp(p.clone()),
+            NodeEvent::NeighborDown(p) => NodeEvent::NeighborDown(p.clone()),
+            NodeEvent::Broadcast(m) => NodeEvent::Broadcast(m.clone()),
+            NodeEvent::Tick(d) => NodeEvent::Tick(*d),
+            NodeEvent::Forget(id) => NodeEvent::Forget(id.clone()),
+        }
+    }
+}
+impl<T: System> fmt::Debug for NodeEvent<T>
+where
+    T::NodeId: fmt::Debug,
+    T::MessageId: fmt::Debug,
+    T::MessagePayload: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NodeEvent::Inbound(m) => write!(f, "Inbound({:?})", m),
+            NodeEvent::NeighborUp(p) => write!(f, "NeighborUp({:?})", p),
+            NodeEvent::NeighborDown(p) => write!(f, "NeighborDown({:?})", p),
+            NodeEvent::Broadcast(m) => write!(f, "Broadcast({:?})", m),
+            NodeEvent::Tick(d) => write!(f, "Tick({:?})", d),
+            NodeEvent::Forget(id) => write!(f, "Forget({:?})", id),
+        }
+    }
+}
+
+/// The provenance of a message kept by Plumtree [Node].
+///
+/// [Node]: ./struct.Node.html
+pub struct MessageProvenance<T: System> {
+    /// The peer from which the node received the message.
+    ///
+    /// This is `None` if the message was broadcast by the node itself.
+    pub received_from: Option<T::NodeId>,
+
+    /// The peers to which the node forwarded the message.
+    pub forwarded_to: Vec<T::NodeId>,
+
+    /// The sender and the round of the `GossipMessage` having the lowest round
+    /// among the ones received for the message (including the duplicate ones).
+    ///
+    /// This is `None` if the message was broadcast by the node itself.
+    pub nearest_sender: Option<(T::NodeId, u16)>,
+}
+impl<T: System> Clone for MessageProvenance<T> {
+    fn clone(&self) -> Self {
+        MessageProvenance {
+            received_from: self.received_from.clone(),
+            forwarded_to: self.forwarded_to.clone(),
+            nearest_sender: self.nearest_sender.clone(),
+        }
+    }
+}
+impl<T: System> fmt::Debug for MessageProvenance<T>
+where
+    T::NodeId: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "MessageProvenance {{ received_from: {:?}, forwarded_to: {:?}, \
+             nearest_sender: {:?} }}",
+            self.received_from, self.forwarded_to, self.nearest_sender
+        )
+    }
+}
+
+/// Optimization threshold of Plumtree [Node].
+///
+/// If a node receives a `GossipMessage` whose round is greater than or equal to
+/// the round of the associated `IhaveMessage` plus this threshold,
+/// the node grafts the sender of the `IhaveMessage` and prunes the sender of the `GossipMessage`
+/// for shortening the path of the spanning tree.
+///
+/// See "3.8. Optimization" of the [paper] for the detailed description of the parameter.
+///
+/// The default value is `2`.
+///
+/// [Node]: ./struct.Node.html
+/// [paper]: http://www.gsd.inesc-id.pt/~ler/reports/srds07.pdf
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OptimizationThreshold(NonZeroU16);
+impl OptimizationThreshold {
+    /// Makes a new `OptimizationThreshold` instance.
+    ///
+    /// If `threshold` is `0`, this method will return `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use plumtree::OptimizationThreshold;
+    ///
+    /// assert!(OptimizationThreshold::new(0).is_none());
+    /// assert_eq!(OptimizationThreshold::new(3).map(|t| t.get()), Some(3));
+    /// ```
+    pub fn new(threshold: u16) -> Option<Self> {
+        NonZeroU16::new(threshold).map(OptimizationThreshold)
+    }
+
+    /// Returns the value of the threshold.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use plumtree::OptimizationThreshold;
+    ///
+    /// assert_eq!(OptimizationThreshold::default().get(), 2);
+    /// ```
+    pub fn get(self) -> u16 {
+        self.0.get()
+    }
+}
+impl Default for OptimizationThreshold {
+    fn default() -> Self {
+        OptimizationThreshold(NonZeroU16::new(2).expect("never fails"))
+    }
+}
+
+type PayloadEq<T> = fn(&<T as System>::MessagePayload, &<T as System>::MessagePayload) -> bool;
+
+type PayloadSize<T> = fn(&<T as System>::MessagePayload) -> usize;
+
+/// The peers that seem to have a message.
+struct Acknowledgement<T: System> {
+    peers: HashSet<T::NodeId>,
+    suggested: bool,
+}
+impl<T: System> Default for Acknowledgement<T> {
+    fn default() -> Self {
+        Acknowledgement {
+            peers: HashSet::new(),
+            suggested: false,
+        }
+    }
+}
+impl<T: System> fmt::Debug for Acknowledgement<T>
+where
+    T::NodeId: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Acknowledgement {{ peers: {:?}, suggested: {:?} }}",
+            self.peers, self.suggested
+        )
+    }
+}
+
+fn inline_size<P>(_: &P) -> usize {
+    std::mem::size_of::<P>()
+}
+
+/// Plumtree node.
+///
+/// # User's responsibility
+///
+/// For running a node correctly, you have to call the following methods appropriately:
+///
+/// - [`poll_action`]
+/// - [`forget_message`]
+/// - [`handle_protocol_message`]
+/// - [`handle_neighbor_up`]
+/// - [`handle_neighbor_down`]
+/// - [`clock_mut`]
+///
+/// For details, refer to the document of each method.
+///
+/// [`poll_action`]: ./struct.Node.html#method.poll_action
+/// [`forget_message`]: ./struct.Node.html#method.forget_message
+/// [`handle_protocol_message`]: ./struct.Node.html#method.handle_protocol_message
+/// [`handle_neighbor_up`]: ./struct.Node.html#method.handle_neighbor_up
+/// [`handle_neighbor_down`]: ./struct.Node.html#method.handle_neighbor_down
+/// [`clock_mut`]: ./struct.Node.html#method.clock_mut
+pub struct Node<T: System> {
+    id: T::NodeId,
+    options: NodeOptions,
+    eager_push_peers: HashSet<T::NodeId>,
+    lazy_push_peers: HashSet<T::NodeId>,
+    messages: HashMap<T::MessageId, T::MessagePayload>,
+    message_seqnos: HashMap<T::MessageId, u64>,
+    received_times: HashMap<T::MessageId, NodeTime>,
+    message_senders: HashMap<T::MessageId, T::NodeId>,
+    message_rounds: HashMap<T::MessageId, u16>,
+    peer_scores: HashMap<T::NodeId, u32>,
+    duplicate_counts: HashMap<T::NodeId, u16>,
+    pruned_by: HashSet<T::NodeId>,
+    provenances: HashMap<T::MessageId, MessageProvenance<T>>,
+    eagerly_forwarded: HashSet<T::MessageId>,
+    originated: HashSet<T::MessageId>,
+    retracted: RecentIds<T::MessageId>,
+    acknowledgements: HashMap<T::MessageId, Acknowledgement<T>>,
+    forgottens: RecentIds<T::MessageId>,
+    removed_peers: RecentIds<T::NodeId>,
+    delivery_times: VecDeque<NodeTime>,
+    round_histogram: HashMap<u16, u64>,
+    peer_changes: Vec<PeerChange<T>>,
+    missings: MissingMessages<T>,
+    delayed_ihaves: DelayedIhaves<T>,
+    outstanding_grafts: OutstandingGrafts<T>,
+    optimization_count: u64,
+    actions: ActionQueue<T>,
+    clock: Clock,
+    transform: Box<dyn PayloadTransform<T> + Send>,
+    policy: Box<dyn TreePolicy<T> + Send>,
+    rng: Box<dyn RngSource + Send>,
+    time_source: Option<Box<dyn TimeSource + Send>>,
+    verifier: Option<Box<dyn Verifier<T> + Send>>,
+    payload_eq: Option<PayloadEq<T>>,
+    payload_size: PayloadSize<T>,
+    payload_conflict_count: u64,
+    dropped_ihave_count: u64,
+    rejected_payload_count: u64,
+    unverified_message_count: u64,
+    probe_seqno: u64,
+    message_seqno: u64,
+    drops: VecDeque<DropEvent<T>>,
+    graft_deadlines: GraftDeadlines<T>,
+    graft_stats: GraftStats,
+    peer_moves: HashMap<T::NodeId, NodeTime>,
+}
+impl<T: System> fmt::Debug for Node<T>
+where
+    T::NodeId: fmt::Debug,
+    T::MessageId: fmt::Debug,
+    T::MessagePayload: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Node {{ id: {:?}, options: {:?}, eager_push_peers: {:?}, lazy_push_peers: {:?}, \
+             messages: {:?}, message_seqnos: {:?}, received_times: {:?}, message_senders: {:?}, message_rounds: {:?}, peer_scores: {:?}, \
+             duplicate_counts: {:?}, pruned_by: {:?}, provenances: {:?}, \
+             eagerly_forwarded: {:?}, originated: {:?}, retracted: {:?}, \
+             acknowledgements: {:?}, forgottens: {:?}, removed_peers: {:?}, delivery_times: {:?}, \
+             round_histogram: {:?}, \
+             peer_changes: {:?}, missings: {:?}, delayed_ihaves: {:?}, \
+             outstanding_grafts: {:?}, \
+             optimization_count: {:?}, actions: {:?}, clock: {:?}, \
+             payload_conflict_count: {:?}, dropped_ihave_count: {:?}, \
+             rejected_payload_count: {:?}, unverified_message_count: {:?}, probe_seqno: {:?}, \
+             message_seqno: {:?}, drops: {:?}, graft_deadlines: {:?}, graft_stats: {:?}, \
+             peer_moves: {:?}, .. }}",
+            self.id,
+            self.options,
+            self.eager_push_peers,
+            self.lazy_push_peers,
+            self.messages,
+            self.message_seqnos,
+            self.received_times,
+            self.message_senders,
+            self.message_rounds,
+            self.peer_scores,
+            self.duplicate_counts,
+            self.pruned_by,
+            self.provenances,
+            self.eagerly_forwarded,
+            self.originated,
+            self.retracted,
+            self.acknowledgements,
+            self.forgottens,
+            self.removed_peers,
+            self.delivery_times,
+            self.round_histogram,
+            self.peer_changes,
+            self.missings,
+            self.delayed_ihaves,
+            self.outstanding_grafts,
+            self.optimization_count,
+            self.actions,
+            self.clock,
+            self.payload_conflict_count,
+            self.dropped_ihave_count,
+            self.rejected_payload_count,
+            self.unverified_message_count,
+            self.probe_seqno,
+            self.message_seqno,
+            self.drops,
+            self.graft_deadlines,
+            self.graft_stats,
+            self.peer_moves
+        )
+    }
+}
+impl<T: System> Node<T> {
+    /// Makes a new `Node` instance.
+    pub fn new(node_id: T::NodeId) -> Self {
+        Self::with_options(node_id, NodeOptions::default())
+    }
+
+    /// Makes a new `Node` instance with the given options.
+    pub fn with_options(node_id: T::NodeId, options: NodeOptions) -> Self {
+        Self::with_options_and_clock(node_id, options, Clock::new())
+    }
+
+    /// Makes a new `Node` instance with the given options and clock.
+    ///
+    /// This is useful for starting a node at a non-zero logical time
+    /// (e.g., when restoring a node or aligning multiple nodes to a shared epoch).
+    pub fn with_options_and_clock(node_id: T::NodeId, options: NodeOptions, clock: Clock) -> Self {
+        let mut actions = ActionQueue::new();
+        actions.advance(clock.now(), options.per_peer_send_rate);
+        Node {
+            id: node_id,
+            eager_push_peers: HashSet::with_capacity(options.expected_peers),
+            lazy_push_peers: HashSet::with_capacity(options.expected_peers),
+            messages: HashMap::with_capacity(options.expected_messages),
+            message_seqnos: HashMap::with_capacity(options.expected_messages),
+            received_times: HashMap::with_capacity(options.expected_messages),
+            options,
+            message_senders: HashMap::new(),
+            message_rounds: HashMap::new(),
+            peer_scores: HashMap::new(),
+            duplicate_counts: HashMap::new(),
+            pruned_by: HashSet::new(),
+            provenances: HashMap::new(),
+            eagerly_forwarded: HashSet::new(),
+            originated: HashSet::new(),
+            retracted: RecentIds::new(),
+            acknowledgements: HashMap::new(),
+            forgottens: RecentIds::new(),
+            removed_peers: RecentIds::new(),
+            delivery_times: VecDeque::new(),
+            round_histogram: HashMap::new(),
+            peer_changes: Vec::new(),
+            missings: MissingMessages::new(),
+            delayed_ihaves: DelayedIhaves::new(),
+            outstanding_grafts: OutstandingGrafts::new(),
+            optimization_count: 0,
+            actions,
+            clock,
+            transform: Box::new(IdentityTransform),
+            policy: Box::new(DefaultTreePolicy),
+            rng: Box::new(SplitMix64::default()),
+            time_source: None,
+            verifier: None,
+            payload_eq: None,
+            payload_size: inline_size,
+            payload_conflict_count: 0,
+            dropped_ihave_count: 0,
+            rejected_payload_count: 0,
+            unverified_message_count: 0,
+            probe_seqno: 0,
+            message_seqno: 0,
+            drops: VecDeque::new(),
+            graft_deadlines: GraftDeadlines::new(),
+            graft_stats: GraftStats::default(),
+            peer_moves: HashMap::new(),
+        }
+    }
+
+    /// Sets the function used for estimating the size of a payload
+    /// by [`approx_memory_bytes`] and [`NodeOptions::max_payload_size`].
+    ///
+    /// The function should return the number of bytes occupied by the given payload,
+    /// including the memory allocated on the heap.
+    ///
+    /// The default function returns `std::mem::size_of::<T::MessagePayload>()`.
+    ///
+    /// [`approx_memory_bytes`]: ./struct.Node.html#method.approx_memory_bytes
+    /// [`NodeOptions::max_payload_size`]: ./struct.NodeOptions.html#structfield.max_payload_size
+    pub fn set_payload_size(&mut self, f: fn(&T::MessagePayload) -> usize) {
+        self.payload_size = f;
+    }
+
+    /// Returns the approximate number of bytes of the memory held by the node.
+    ///
+    /// This sums the sizes of the kept messages (the size of each payload is estimated by
+    /// the function set by [`set_payload_size`]), the peer sets and the waiting `IHAVE` messages.
+    /// The overhead of the internal data structures (e.g., hash tables) is not taken into account.
+    ///
+    /// [`set_payload_size`]: ./struct.Node.html#method.set_payload_size
+    pub fn approx_memory_bytes(&self) -> usize {
+        let messages = self.messages.len() * std::mem::size_of::<T::MessageId>()
+            + self
+                .messages
+                .values()
+                .map(|p| (self.payload_size)(p))
+                .sum::<usize>();
+        let peers = (self.eager_push_peers.len() + self.lazy_push_peers.len())
+            * std::mem::size_of::<T::NodeId>();
+        messages + peers + self.missings.approx_memory_bytes()
+    }
+
+    /// Takes a snapshot of the reconstructable state of the node.
+    ///
+    /// See [NodeState] for the details.
+    ///
+    /// [NodeState]: ./struct.NodeState.html
+    pub fn export_state(&self) -> NodeState<T> {
+        let mut state = NodeState::new(self.id.clone(), self.options.clone(), self.clock.clone());
+        state.eager_push_peers = self.eager_push_peers.iter().cloned().collect();
+        state.lazy_push_peers = self.lazy_push_peers.iter().cloned().collect();
+        state.messages = self
+            .stored_message_ids(ResyncOrder::OldestFirst)
+            .into_iter()
+            .map(|id| {
+                let payload = self.transform.load(&self.messages[&id]);
+                (id, payload)
+            })
+            .collect();
+        state.originated = self.originated.iter().cloned().collect();
+        state.rounds = self
+            .message_rounds
+            .iter()
+            .map(|(id, round)| (id.clone(), *round))
+            .collect();
+        state.missings = self
+            .missings
+            .owners()
+            .map(|(id, owners)| (id.clone(), owners.to_vec()))
+            .collect();
+        state
+    }
+
+    /// Makes a new `Node` instance from the given snapshot taken by [`export_state`].
+    ///
+    /// If the version of the snapshot is incompatible with this crate,
+    /// or the peers in the snapshot are invalid in the same way as rejected by [`set_peers`]
+    /// (i.e., the node itself is contained or a peer is both eager and lazy),
+    /// this method will return an error.
+    ///
+    /// [`set_peers`]: ./struct.Node.html#method.set_peers
+    /// [`export_state`]: ./struct.Node.html#method.export_state
+    pub fn import_state(state: NodeState<T>) -> Result<Self, String> {
+        if state.version() != NodeState::<T>::VERSION {
+            return Err(format!(
+                "Incompatible snapshot version: expected={}, actual={}",
+                NodeState::<T>::VERSION,
+                state.version()
+            ));
+        }
+
+        let mut node = Self::with_options_and_clock(state.id, state.options, state.clock);
+        let eager = state.eager_push_peers.into_iter().collect();
+        let lazy = state.lazy_push_peers.into_iter().collect();
+        if !node.set_peers(eager, lazy) {
+            return Err("Invalid peers in snapshot".to_owned());
+        }
+        for (id, payload) in state.messages {
+            let payload = node.transform.store(payload);
+            node.store_message(id, payload);
+        }
+        for id in state.originated {
+            node.message_rounds.insert(id.clone(), 0);
+            node.originated.insert(id);
+        }
+        for (id, round) in state.rounds {
+            if node.messages.contains_key(&id) {
+                node.message_rounds.insert(id, round);
+            }
+        }
+        for (id, owners) in state.missings {
+            for (owner, round) in owners {
+                let ihave = IhaveMessage::new(&owner, id.clone(), round, false);
+                node.missings.push(
+                    ihave,
+                    &node.clock,
+                    node.options.ihave_timeout,
+                    node.options.realtime_ihave_timeout,
+                    node.options.max_backup_owners.unwrap_or(usize::MAX),
+                );
+            }
+        }
+        Ok(node)
+    }
+
+    /// Returns the identifier of the node.
+    pub fn id(&self) -> &T::NodeId {
+        &self.id
+    }
+
+    /// Changes the identifier of the node.
+    ///
+    /// This is useful when a standby node takes over the identity of a failed node.
+    /// The subsequent outgoing messages carry the new identifier as their sender,
+    /// but the already queued actions are not changed.
+    /// Note that the peers of the node are not notified of the change,
+    /// so it must be told to them separately.
+    ///
+    /// If `new_id` is a peer of the node, this method will return `false` without changing the identifier.
+    pub fn set_id(&mut self, new_id: T::NodeId) -> bool {
+        if self.is_known_node(&new_id) {
+            return false;
+        }
+        self.id = new_id;
+        true
+    }
+
+    /// Returns the options of the node.
+    pub fn options(&self) -> &NodeOptions {
+        &self.options
+    }
+
+    /// Returns a mutable reference to the options of the node.
+    pub fn options_mut(&mut self) -> &mut NodeOptions {
+        &mut self.options
+    }
+
+    /// Sets the observer which is invoked each time the node enqueues a `Send` action.
+    ///
+    /// The observer receives the destination and the protocol message of the action.
+    /// This is useful for recording metrics or traces of outgoing messages.
+    pub fn set_send_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(&T::NodeId, &ProtocolMessage<T>) + Send + 'static,
+    {
+        self.actions.set_send_observer(Box::new(observer));
+    }
+
+    /// Sets the transform applied to the payloads of the messages kept by the node.
+    ///
+    /// This should be called before the node receives any messages
+    /// because the messages that have already been kept are not converted.
+    ///
+    /// The default transform is [IdentityTransform].
+    ///
+    /// [IdentityTransform]: ./struct.IdentityTransform.html
+    pub fn set_payload_transform<X>(&mut self, transform: X)
+    where
+        X: PayloadTransform<T> + Send + 'static,
+    {
+        self.transform = Box::new(transform);
+    }
+
+    /// Sets the verifier of the incoming messages.
+    ///
+    /// The messages for which the verifier returns `false` are dropped before being handled
+    /// (see [`HandleError::Unverified`]) and counted by [`unverified_message_count`].
+    ///
+    /// By default, the node does not verify incoming messages.
+    ///
+    /// [`HandleError::Unverified`]: ./enum.HandleError.html#variant.Unverified
+    /// [`unverified_message_count`]: ./struct.Node.html#method.unverified_message_count
+    pub fn set_verifier<V>(&mut self, verifier: V)
+    where
+        V: Verifier<T> + Send + 'static,
+    {
+        self.verifier = Some(Box::new(verifier));
+    }
+
+    /// Sets the policy for the reconfiguration decisions of the spanning tree.
+    ///
+    /// The default policy is [DefaultTreePolicy].
+    ///
+    /// [DefaultTreePolicy]: ./struct.DefaultTreePolicy.html
+    pub fn set_tree_policy<P>(&mut self, policy: P)
+    where
+        P: TreePolicy<T> + Send + 'static,
+    {
+        self.policy = Box::new(policy);
+    }
+
+    /// Sets the random number generator of the node.
+    ///
+    /// The default generator is [`SplitMix64`] with the seed `0`.
+    ///
+    /// [`SplitMix64`]: ./rng/struct.SplitMix64.html
+    pub fn set_rng<R>(&mut self, rng: R)
+    where
+        R: RngSource + Send + 'static,
+    {
+        self.rng = Box::new(rng);
+    }
+
+    /// Sets the time source which drives the clock of the node.
+    ///
+    /// Once set, the clock of the node proceeds to the time of the source each time
+    /// an action is polled, a message is handled or a message is broadcast.
+    /// [`clock_mut`] can still be used, but the clock never goes backward.
+    ///
+    /// By default, the node has no time source and its clock has to be ticked manually.
+    ///
+    /// [`clock_mut`]: ./struct.Node.html#method.clock_mut
+    pub fn set_time_source<S>(&mut self, source: S)
+    where
+        S: TimeSource + Send + 'static,
+    {
+        self.clock.tick_to(source.now());
+        self.time_source = Some(Box::new(source));
+    }
+
+    /// Returns a mutable reference to the random number generator of the node.
+    ///
+    /// This allows applications and simulators to share the reproducible random source of the node.
+    pub fn rng_mut(&mut self) -> &mut (dyn RngSource + Send) {
+        &mut *self.rng
+    }
+
+    /// Updates [`NodeOptions::ihave_timeout`] and reschedules the waiting `IHAVE` messages.
+    ///
+    /// The remaining time until the expiration of each waiting `IHAVE` message is rescaled
+    /// proportionally (e.g., halving the timeout halves the remaining time).
+    /// A rescaled expiration time that would overflow saturates at the maximum `NodeTime`.
+    /// If the old timeout is zero, the waiting `IHAVE` messages are left as they are,
+    /// because there is no meaningful ratio to rescale them by.
+    ///
+    /// Note that updating the option via [`options_mut`] only affects `IHAVE` messages received after that.
+    ///
+    /// [`NodeOptions::ihave_timeout`]: ./struct.NodeOptions.html#structfield.ihave_timeout
+    /// [`options_mut`]: ./struct.Node.html#method.options_mut
+    pub fn set_ihave_timeout(&mut self, timeout: Duration) {
+        let old = self.options.ihave_timeout;
+        self.options.ihave_timeout = timeout;
+        if old != Duration::from_millis(0) {
+            let ratio = timeout.as_secs_f64() / old.as_secs_f64();
+            self.missings.rescale_timeouts(&self.clock, ratio);
+        }
+    }
+
+    /// Updates [`NodeOptions::per_peer_send_rate`] and applies it to the throttled sends immediately.
+    ///
+    /// For example, setting `None` releases all the throttled sends as `Send` actions.
+    /// Note that updating the option via [`options_mut`] takes effect at the next [`poll_action`] call.
+    ///
+    /// [`NodeOptions::per_peer_send_rate`]: ./struct.NodeOptions.html#structfield.per_peer_send_rate
+    /// [`options_mut`]: ./struct.Node.html#method.options_mut
+    /// [`poll_action`]: ./struct.Node.html#method.poll_action
+    pub fn set_per_peer_send_rate(&mut self, rate: Option<(usize, Duration)>) {
+        self.options.per_peer_send_rate = rate;
+        self.sync_clock();
+        self.actions.advance(self.clock.now(), rate);
+    }
+
+    /// Returns the peers with which the node uses eager push gossip for diffusing application messages.
+    pub fn eager_push_peers(&self) -> &HashSet<T::NodeId> {
+        &self.eager_push_peers
+    }
+
+    /// Returns the peers with which the node uses lazy push gossip for diffusing application messages.
+    pub fn lazy_push_peers(&self) -> &HashSet<T::NodeId> {
+        &self.lazy_push_peers
+    }
+
+    /// Returns the number of the neighbors (i.e., the eager and lazy push peers) of the node.
+    pub fn neighbor_count(&self) -> usize {
+        self.eager_push_peers.len() + self.lazy_push_peers.len()
     }
 
-    /// Broadcasts the given message.
-    pub fn broadcast_message(&mut self, message: Message<T>) {
-        self.actions.deliver(message.clone());
-
-        let gossip = GossipMessage::new(&self.id, message, 0);
-        self.eager_push(&gossip);
-        self.lazy_push(&gossip);
-        self.messages
-            .insert(gossip.message.id, gossip.message.payload);
+    /// Returns the number of the eager push peers of the node.
+    pub fn eager_count(&self) -> usize {
+        self.eager_push_peers.len()
+    }
+
+    /// Returns the number of the lazy push peers of the node.
+    pub fn lazy_count(&self) -> usize {
+        self.lazy_push_peers.len()
+    }
+
+    /// Replaces the eager and lazy push peers of the node.
+    ///
+    /// Unlike [`handle_neighbor_up`] and [`handle_neighbor_down`],
+    /// this method does not send any protocol messages.
+    ///
+    /// If `eager` and `lazy` are not disjoint or contain the node itself,
+    /// this method will return `false` without changing the peers.
+    ///
+    /// [`handle_neighbor_up`]: ./struct.Node.html#method.handle_neighbor_up
+    /// [`handle_neighbor_down`]: ./struct.Node.html#method.handle_neighbor_down
+    pub fn set_peers(&mut self, eager: HashSet<T::NodeId>, lazy: HashSet<T::NodeId>) -> bool {
+        if !eager.is_disjoint(&lazy) || eager.contains(&self.id) || lazy.contains(&self.id) {
+            return false;
+        }
+
+        let removed = self
+            .eager_push_peers
+            .iter()
+            .chain(self.lazy_push_peers.iter())
+            .filter(|p| !eager.contains(*p) && !lazy.contains(*p))
+            .cloned()
+            .collect::<Vec<_>>();
+        for peer in &removed {
+            self.remove_peer(peer);
+        }
+        for peer in &eager {
+            self.make_eager(peer);
+        }
+        for peer in &lazy {
+            self.make_lazy(peer);
+        }
+        true
+    }
+
+    /// Merges the given view of the eager and lazy push peers into the peers of the node.
+    ///
+    /// The peers are unioned, and a peer which is an eager push peer in either view
+    /// becomes an eager push peer.
+    /// Like [`set_peers`], this method does not send any protocol messages.
+    ///
+    /// If `other_eager` or `other_lazy` contains the node itself,
+    /// this method will return `false` without changing the peers.
+    ///
+    /// [`set_peers`]: ./struct.Node.html#method.set_peers
+    pub fn merge_peers(
+        &mut self,
+        other_eager: &HashSet<T::NodeId>,
+        other_lazy: &HashSet<T::NodeId>,
+    ) -> bool {
+        if other_eager.contains(&self.id) || other_lazy.contains(&self.id) {
+            return false;
+        }
+
+        for peer in other_eager {
+            self.make_eager(peer);
+        }
+        for peer in other_lazy {
+            if !self.is_known_node(peer) {
+                self.make_lazy(peer);
+            }
+        }
+        true
+    }
+
+    /// Takes the changes of the eager and lazy push peers recorded since the last call.
+    ///
+    /// The changes are returned in the order they occurred.
+    /// If [`NodeOptions::track_peer_changes`] is `false`, this method will return an empty vector.
+    ///
+    /// [`NodeOptions::track_peer_changes`]: ./struct.NodeOptions.html#structfield.track_peer_changes
+    pub fn take_peer_changes(&mut self) -> Vec<PeerChange<T>> {
+        std::mem::take(&mut self.peer_changes)
+    }
+
+    /// Moves the given peer from the lazy push peers to the eager push peers.
+    ///
+    /// A `GraftMessage` is sent to the peer for notifying the change.
+    ///
+    /// If the peer is not a neighbor of this node, is already an eager push peer
+    /// or [`NodeOptions::pull_only`] is `true`, this method will return `false`.
+    ///
+    /// [`NodeOptions::pull_only`]: ./struct.NodeOptions.html#structfield.pull_only
+    pub fn promote_to_eager(&mut self, peer: &T::NodeId) -> bool {
+        if !self.lazy_push_peers.contains(peer) || self.options.pull_only {
+            return false;
+        }
+        self.make_eager(peer);
+        self.actions
+            .send(peer.clone(), GraftMessage::new(&self.id, None, 0));
+        true
+    }
+
+    /// Moves the given peer from the eager push peers to the lazy push peers.
+    ///
+    /// A `PruneMessage` is sent to the peer for notifying the change.
+    ///
+    /// If the peer is not a neighbor of this node or is already a lazy push peer,
+    /// this method will return `false`.
+    pub fn demote_to_lazy(&mut self, peer: &T::NodeId) -> bool {
+        if !self.eager_push_peers.contains(peer) {
+            return false;
+        }
+        self.make_lazy(peer);
+        self.actions.send(peer.clone(), PruneMessage::new(&self.id));
+        true
+    }
+
+    /// Returns an iterator over the peers that have pruned this node.
+    ///
+    /// Unlike [`lazy_push_peers`], which are the peers to which this node sends only `IhaveMessage`s,
+    /// these are the peers that (presumably) no longer send `GossipMessage`s to this node eagerly.
+    /// A peer is removed from the list when it sends a `GossipMessage` to this node
+    /// or this node sends a `GraftMessage` to it.
+    ///
+    /// [`lazy_push_peers`]: ./struct.Node.html#method.lazy_push_peers
+    pub fn peers_that_pruned_us(&self) -> impl Iterator<Item = &T::NodeId> {
+        self.pruned_by.iter()
+    }
+
+    /// Returns the reliability score of the given peer.
+    ///
+    /// If no score has been set for the peer, this method will return `0`.
+    pub fn peer_score(&self, peer: &T::NodeId) -> u32 {
+        self.peer_scores.get(peer).copied().unwrap_or(0)
+    }
+
+    /// Sets the reliability score of the given peer.
+    ///
+    /// When the node detects redundant eager push links,
+    /// the link to the peer with the lower score is pruned preferentially.
+    pub fn set_peer_score(&mut self, peer: &T::NodeId, score: u32) {
+        self.peer_scores.insert(peer.clone(), score);
+    }
+
+    /// Broadcasts the given message.
+    ///
+    /// If the payload of the message exceeds [`NodeOptions::max_payload_size`],
+    /// the message is dropped.
+    ///
+    /// [`NodeOptions::max_payload_size`]: ./struct.NodeOptions.html#structfield.max_payload_size
+    pub fn broadcast_message(&mut self, message: Message<T>) {
+        self.sync_clock();
+        if self.is_oversized(&message.payload) {
+            self.rejected_payload_count += 1;
+            self.record_drop(DropReason::OversizedPayload, None, Some(&message.id));
+            return;
+        }
+        self.originated.insert(message.id.clone());
+        self.flood(message, 0);
+    }
+
+    /// Broadcasts the given message as if it had been received at the given round.
+    ///
+    /// This is useful for re-injecting a message received from another node (e.g., for redundancy)
+    /// without making the node look like the origin of the message.
+    /// The forwarded `GossipMessage`s and `IhaveMessage`s carry `round + 1`.
+    ///
+    /// If the node already keeps the message, it is only forwarded (i.e., not delivered and stored again).
+    /// Otherwise, the message is delivered and stored as same as [`broadcast_message`].
+    ///
+    /// [`broadcast_message`]: ./struct.Node.html#method.broadcast_message
+    pub fn rebroadcast_message(&mut self, message: Message<T>, round: u16) {
+        self.sync_clock();
+        if self.is_oversized(&message.payload) {
+            self.rejected_payload_count += 1;
+            self.record_drop(DropReason::OversizedPayload, None, Some(&message.id));
+            return;
+        }
+        if self.messages.contains_key(&message.id) {
+            let gossip = GossipMessage::new(&self.id, message, round);
+            self.eager_push(&gossip);
+            self.lazy_push(&gossip);
+            return;
+        }
+        self.missings.remove(&message.id);
+        self.outstanding_grafts.remove(&message.id);
+        self.flood(message, round);
+    }
+
+    /// Stores the given message without delivering or forwarding it.
+    ///
+    /// This is useful for seeding the node with messages which the application already has.
+    /// The stored message is served to the peers requesting it by `GraftMessage`s,
+    /// and announced to new neighbors like the other kept messages.
+    ///
+    /// If the node already keeps the message, its payload is replaced.
+    pub fn insert_message_silent(&mut self, message: Message<T>) {
+        self.missings.remove(&message.id);
+        self.outstanding_grafts.remove(&message.id);
+        self.forgottens.remove(&message.id);
+        let payload = self.transform.store(message.payload);
+        self.store_message(message.id, payload);
+    }
+
+    /// Requests the specified message to the given peer by sending a `GraftMessage`.
+    ///
+    /// This is useful when the application knows the existence of a message in some way
+    /// other than `IhaveMessage`s (e.g., via a separate index).
+    /// The `GossipMessage` sent from the peer as the reply will be delivered as a new message
+    /// even if the message has been forgotten by the node.
+    ///
+    /// If the peer is not a neighbor of this node or the node already has the message,
+    /// this method will return `false`.
+    pub fn request_message(&mut self, peer: &T::NodeId, message_id: T::MessageId) -> bool {
+        if !self.is_known_node(peer) || self.messages.contains_key(&message_id) {
+            return false;
+        }
+        self.forgottens.remove(&message_id);
+        let round = self.missings.get_ihave(&message_id).map_or(0, |(r, _)| r);
+        let ihave = IhaveMessage::new(peer, message_id.clone(), round, false);
+        self.send_graft(ihave);
+        if self.options.max_graft_owners_per_message.is_some() {
+            let deadline = self.clock.now() + self.options.ihave_timeout;
+            self.outstanding_grafts
+                .record(message_id, peer.clone(), deadline);
+        }
+        true
     }
 
     /// Returns a reference to the messages kept by the node.
+    ///
+    /// Note that the payloads are the ones converted by the [PayloadTransform] of the node.
+    ///
+    /// [PayloadTransform]: ./trait.PayloadTransform.html
     pub fn messages(&self) -> &HashMap<T::MessageId, T::MessagePayload> {
         &self.messages
     }
 
+    /// Returns the identifiers of the kept messages with the rounds at which they were received.
+    ///
+    /// The result is sorted in ascending order of the rounds (i.e., the messages received closest
+    /// to their sources come first), and the messages of the same round are sorted in the order they were stored.
+    /// The messages broadcast by the node itself have the round `0`.
+    /// The messages inserted by [`insert_message_silent`] are not included because their rounds are unknown.
+    ///
+    /// [`insert_message_silent`]: ./struct.Node.html#method.insert_message_silent
+    pub fn messages_by_round(&self) -> Vec<(&T::MessageId, u16)> {
+        let mut messages = self
+            .message_seqnos
+            .iter()
+            .filter_map(|(id, seqno)| self.message_rounds.get(id).map(|r| (id, *r, *seqno)))
+            .collect::<Vec<_>>();
+        messages.sort_by_key(|x| (x.1, x.2));
+        messages.into_iter().map(|(id, r, _)| (id, r)).collect()
+    }
+
+    /// Returns `true` if the node keeps the specified message, otherwise `false`.
+    pub fn has_message(&self, message_id: &T::MessageId) -> bool {
+        self.messages.contains_key(message_id)
+    }
+
+    /// Returns the time when the node stored the specified message (by broadcasting or receiving it).
+    ///
+    /// If the node does not keep the message, this method will return `None`.
+    pub fn received_at(&self, message_id: &T::MessageId) -> Option<NodeTime> {
+        self.received_times.get(message_id).copied()
+    }
+
     /// Returns the number of messages waiting to be received.
     ///
     /// Roughly speaking, it indicates the approximate number of `IHAVE` messages held by the node.
@@ -165,13 +1645,327 @@ impl<T: System> Node<T> {
         self.missings.waiting_messages()
     }
 
+    /// Returns an iterator over the `IhaveMessage`s which are pending to be sent to the given peer.
+    ///
+    /// Each item is a pair of the identifier of the announced message and its round.
+    ///
+    /// The announcements are buffered only if [`NodeOptions::lazy_suppression_window`] is non-zero,
+    /// otherwise the iterator is always empty.
+    ///
+    /// [`NodeOptions::lazy_suppression_window`]: ./struct.NodeOptions.html#structfield.lazy_suppression_window
+    pub fn pending_lazy_announcements<'a>(
+        &'a self,
+        peer: &'a T::NodeId,
+    ) -> impl Iterator<Item = (&'a T::MessageId, u16)> + 'a {
+        self.delayed_ihaves.pendings_for(peer)
+    }
+
+    /// Returns the number of `IhaveMessage`s dropped due to [`NodeOptions::max_waiting_messages`].
+    ///
+    /// [`NodeOptions::max_waiting_messages`]: ./struct.NodeOptions.html#structfield.max_waiting_messages
+    pub fn dropped_ihave_count(&self) -> u64 {
+        self.dropped_ihave_count
+    }
+
+    /// Returns the number of messages rejected due to [`NodeOptions::max_payload_size`].
+    ///
+    /// [`NodeOptions::max_payload_size`]: ./struct.NodeOptions.html#structfield.max_payload_size
+    pub fn rejected_payload_count(&self) -> u64 {
+        self.rejected_payload_count
+    }
+
+    /// Returns the number of incoming messages rejected by the verifier (see [`set_verifier`]).
+    ///
+    /// [`set_verifier`]: ./struct.Node.html#method.set_verifier
+    pub fn unverified_message_count(&self) -> u64 {
+        self.unverified_message_count
+    }
+
+    /// Returns an iterator over the most recently dropped messages (oldest first).
+    ///
+    /// The drops include the incoming messages rejected due to their senders,
+    /// the messages having oversized payloads and the `IhaveMessage`s discarded due to the waiting limit.
+    /// At most [`NodeOptions::drop_history`] events are retained.
+    ///
+    /// [`NodeOptions::drop_history`]: ./struct.NodeOptions.html#structfield.drop_history
+    pub fn recent_drops(&self) -> vec_deque::Iter<'_, DropEvent<T>> {
+        self.drops.iter()
+    }
+
+    /// Returns the identifiers of the messages that the node has been waiting for
+    /// `threshold` or longer since the first `IHAVE` message was received
+    /// and that have no untried owners among the current neighbors.
+    ///
+    /// Such messages may not be received via the protocol
+    /// (e.g., because the owners of the messages have gone away),
+    /// so the application may need to fetch them in some other way.
+    pub fn stuck_messages(&self, threshold: Duration) -> Vec<T::MessageId> {
+        self.missings
+            .stuck_messages(&self.clock, threshold, |n| self.is_known_node(n))
+    }
+
+    /// Stops waiting the messages reported by [`stuck_messages`] and returns their identifiers.
+    ///
+    /// [`stuck_messages`]: ./struct.Node.html#method.stuck_messages
+    pub fn clear_stuck_messages(&mut self, threshold: Duration) -> Vec<T::MessageId> {
+        let stucks = self.stuck_messages(threshold);
+        for message_id in &stucks {
+            self.missings.remove(message_id);
+            self.outstanding_grafts.remove(message_id);
+        }
+        stucks
+    }
+
+    /// Returns the number of items in the internal timeout queue of `IHAVE` messages.
+    ///
+    /// Unlike [`waiting_messages`], this counts every queued `IHAVE` message
+    /// (i.e., a message advertised by multiple neighbors is counted multiple times).
+    ///
+    /// [`waiting_messages`]: ./struct.Node.html#method.waiting_messages
+    pub fn timeout_queue_len(&self) -> usize {
+        self.missings.timeout_queue_len()
+    }
+
+    /// Returns the total number of `Deliver` actions issued by the node.
+    ///
+    /// Unlike `messages().len()`, this also counts the messages that have been forgotten.
+    /// Thus it can be used as a monotonic delivery sequence number of the node.
+    pub fn delivered_count(&self) -> u64 {
+        self.actions.delivered_count()
+    }
+
+    /// Returns the number of messages delivered per second during the last `window`.
+    ///
+    /// The rate is computed based on the logical time of the clock of the node.
+    /// Only the deliveries within [`NodeOptions::delivery_rate_window`] are recorded,
+    /// so `window` is truncated to the value of the option.
+    ///
+    /// If the option is zero, this method will always return `0.0`.
+    ///
+    /// [`NodeOptions::delivery_rate_window`]: ./struct.NodeOptions.html#structfield.delivery_rate_window
+    pub fn recent_delivery_rate(&self, window: Duration) -> f64 {
+        let window = window.min(self.options.delivery_rate_window);
+        if window == Duration::from_millis(0) {
+            return 0.0;
+        }
+        let count = self
+            .delivery_times
+            .iter()
+            .filter(|&&t| self.clock.elapsed_since(t) < window)
+            .count();
+        count as f64 / window.as_secs_f64()
+    }
+
+    /// Returns the histogram of the rounds (i.e., hop counts) at which messages were delivered.
+    ///
+    /// Each entry maps a round to the number of the messages delivered at the round.
+    /// The round of a message broadcast by this node is `0`.
+    ///
+    /// If [`NodeOptions::track_round_histogram`] is `false`, the histogram is always empty.
+    ///
+    /// [`NodeOptions::track_round_histogram`]: ./struct.NodeOptions.html#structfield.track_round_histogram
+    pub fn round_histogram(&self) -> &HashMap<u16, u64> {
+        &self.round_histogram
+    }
+
+    /// Returns the provenance of the specified message.
+    ///
+    /// If [`NodeOptions::track_provenance`] is `false` or
+    /// the node does not have the target message, this method will return `None`.
+    ///
+    /// [`NodeOptions::track_provenance`]: ./struct.NodeOptions.html#structfield.track_provenance
+    pub fn message_provenance(&self, message_id: &T::MessageId) -> Option<&MessageProvenance<T>> {
+        self.provenances.get(message_id)
+    }
+
+    /// Returns the node inferred as the origin of the specified message.
+    ///
+    /// The origin is inferred as the sender of the `GossipMessage` having the lowest round among
+    /// the ones received for the message (see [`MessageProvenance::nearest_sender`]).
+    /// This is a heuristic: the inference is correct only if the node has received the message
+    /// directly from the origin (i.e., with the round `1`), which is possible only for the neighbors of the origin.
+    /// Otherwise, the result is the neighbor nearest to the origin known by the node.
+    /// If the message has been broadcast by the node itself, this method returns the identifier of the node.
+    ///
+    /// If [`NodeOptions::track_provenance`] is `false` or
+    /// the node does not have the target message, this method will return `None`.
+    ///
+    /// [`MessageProvenance::nearest_sender`]: ./struct.MessageProvenance.html#structfield.nearest_sender
+    /// [`NodeOptions::track_provenance`]: ./struct.NodeOptions.html#structfield.track_provenance
+    pub fn inferred_origin(&self, message_id: &T::MessageId) -> Option<&T::NodeId> {
+        let provenance = self.provenances.get(message_id)?;
+        match provenance.nearest_sender.as_ref() {
+            Some((sender, _)) => Some(sender),
+            None => Some(&self.id),
+        }
+    }
+
+    /// Returns `true` if the node has forwarded the specified message to at least one eager push peer.
+    ///
+    /// In other words, the node is an internal node of the spanning tree of the message
+    /// if this method returns `true`, and a leaf node otherwise.
+    ///
+    /// If the node does not have the target message, this method will return `false`.
+    pub fn forwarded_eagerly(&self, message_id: &T::MessageId) -> bool {
+        self.eagerly_forwarded.contains(message_id)
+    }
+
+    /// Returns `true` if the specified message has been broadcast by this node,
+    /// otherwise (e.g., the message has been received from a peer) `false`.
+    ///
+    /// If the node does not have the target message, this method will return `false`.
+    pub fn is_originator(&self, message_id: &T::MessageId) -> bool {
+        self.originated.contains(message_id)
+    }
+
+    /// Returns the number of the messages kept by the node that have been broadcast by the node itself.
+    ///
+    /// See also [`is_originator`].
+    ///
+    /// [`is_originator`]: ./struct.Node.html#method.is_originator
+    pub fn originated_count(&self) -> usize {
+        self.originated
+            .iter()
+            .filter(|id| self.messages.contains_key(id))
+            .count()
+    }
+
+    /// Returns the number of times the node has decided to optimize the spanning tree.
+    ///
+    /// See [`OptimizationThreshold`] for the optimization.
+    /// This includes the decisions made in the dry-run mode (see [`NodeOptions::optimization_dry_run`]).
+    ///
+    /// [`OptimizationThreshold`]: ./struct.OptimizationThreshold.html
+    /// [`NodeOptions::optimization_dry_run`]: ./struct.NodeOptions.html#structfield.optimization_dry_run
+    pub fn optimization_count(&self) -> u64 {
+        self.optimization_count
+    }
+
+    /// Returns the number of duplicate `GossipMessage`s whose payloads differ from the kept ones.
+    ///
+    /// This is always `0` unless [`detect_payload_conflicts`] is enabled.
+    ///
+    /// [`detect_payload_conflicts`]: ./struct.Node.html#method.detect_payload_conflicts
+    pub fn payload_conflict_count(&self) -> u64 {
+        self.payload_conflict_count
+    }
+
+    /// Returns the statistics of the `GraftMessage`s sent by the node for requesting messages.
+    ///
+    /// Note that the timed out requests are counted when [`poll_action`] is called after the timeouts.
+    ///
+    /// [`poll_action`]: ./struct.Node.html#method.poll_action
+    pub fn graft_stats(&self) -> GraftStats {
+        self.graft_stats
+    }
+
     /// Forgets the specified message.
     ///
     /// If the node does not have the target message, this method will return `false`.
     ///
     /// For preventing memory shortage, this method needs to be called appropriately.
     pub fn forget_message(&mut self, message_id: &T::MessageId) -> bool {
-        self.messages.remove(message_id).is_some()
+        self.message_senders.remove(message_id);
+        self.message_rounds.remove(message_id);
+        self.provenances.remove(message_id);
+        self.eagerly_forwarded.remove(message_id);
+        self.originated.remove(message_id);
+        self.acknowledgements.remove(message_id);
+        self.message_seqnos.remove(message_id);
+        self.received_times.remove(message_id);
+        if self.messages.remove(message_id).is_some() {
+            let capacity = self.options.forgotten_message_memory;
+            self.forgottens.push(message_id.clone(), capacity);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Retracts the specified message.
+    ///
+    /// In addition to forgetting the message (see [`forget_message`]),
+    /// the node cancels the pending announcements of the message
+    /// and replies to subsequent `GraftMessage`s for it with `PruneMessage`s instead of `GossipMessage`s.
+    /// Note that the `GossipMessage`s that have already been sent cannot be retracted.
+    ///
+    /// The retraction is cancelled if the node stores the message again (e.g., by [`broadcast_message`]).
+    /// At most [`NodeOptions::retracted_message_memory`] retractions are remembered.
+    ///
+    /// If the node does not have the target message, this method will return `false`.
+    ///
+    /// [`forget_message`]: ./struct.Node.html#method.forget_message
+    /// [`broadcast_message`]: ./struct.Node.html#method.broadcast_message
+    /// [`NodeOptions::retracted_message_memory`]: ./struct.NodeOptions.html#structfield.retracted_message_memory
+    pub fn retract_message(&mut self, message_id: &T::MessageId) -> bool {
+        if !self.forget_message(message_id) {
+            return false;
+        }
+        self.delayed_ihaves.cancel(message_id);
+        let capacity = self.options.retracted_message_memory;
+        self.retracted.push(message_id.clone(), capacity);
+        true
+    }
+
+    /// Forgets all the messages kept by the node.
+    ///
+    /// The waiting `IHAVE` messages and the outstanding `GRAFT` messages are also discarded.
+    /// Unlike [`shutdown`], the peers, the options and the clock of the node are left intact.
+    ///
+    /// This method returns the number of the forgotten messages kept by the node.
+    ///
+    /// [`shutdown`]: ./struct.Node.html#method.shutdown
+    pub fn forget_all(&mut self) -> usize {
+        let ids = self.messages.keys().cloned().collect::<Vec<_>>();
+        for id in &ids {
+            self.forget_message(id);
+        }
+        self.missings = MissingMessages::new();
+        self.delayed_ihaves = DelayedIhaves::new();
+        self.outstanding_grafts = OutstandingGrafts::new();
+        self.graft_deadlines.clear();
+        ids.len()
+    }
+
+    /// Shuts down the node gracefully.
+    ///
+    /// This method sends `PruneMessage`s to all the eager push peers
+    /// so that they stop forwarding messages to this node,
+    /// and then clears the peers, the kept messages and the waiting `IHAVE` messages.
+    ///
+    /// The returned actions, which include the ones queued before calling this method,
+    /// should be executed before disconnecting from the neighbors.
+    pub fn shutdown(&mut self) -> Vec<Action<T>> {
+        let eager_push_peers = self.eager_push_peers.iter().cloned().collect::<Vec<_>>();
+        for peer in eager_push_peers {
+            self.actions.send(peer, PruneMessage::new(&self.id));
+        }
+        let peers = self
+            .eager_push_peers
+            .iter()
+            .chain(self.lazy_push_peers.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+        for peer in &peers {
+            self.remove_peer(peer);
+        }
+        self.messages.clear();
+        self.message_seqnos.clear();
+        self.received_times.clear();
+        self.message_senders.clear();
+        self.message_rounds.clear();
+        self.provenances.clear();
+        self.eagerly_forwarded.clear();
+        self.originated.clear();
+        self.retracted.clear();
+        self.acknowledgements.clear();
+        self.forgottens.clear();
+        self.missings = MissingMessages::new();
+        self.delayed_ihaves = DelayedIhaves::new();
+        self.outstanding_grafts = OutstandingGrafts::new();
+        self.graft_deadlines.clear();
+        self.actions.release_all();
+        DrainActions::new(&mut self.actions).collect()
     }
 
     /// Polls the next action that the node wants to execute.
@@ -180,20 +1974,146 @@ impl<T: System> Node<T> {
         self.actions.pop()
     }
 
-    /// Handles the given incoming message.
+    /// Returns an iterator that drains the actions queued in the node.
+    ///
+    /// Expired `IHAVE` messages are handled only once when this method is called,
+    /// so the iterator yields the actions until the queue becomes empty.
+    ///
+    /// `for action in &mut node { .. }` is equivalent to `for action in node.drain_actions() { .. }`.
+    pub fn drain_actions(&mut self) -> DrainActions<'_, T> {
+        self.handle_expiration();
+        DrainActions::new(&mut self.actions)
+    }
+
+    /// Returns the number of actions queued in the node.
+    ///
+    /// Like [`peek_actions`], this does not include the actions
+    /// that will be caused by expired `IHAVE` messages at the next [`poll_action`] call.
+    ///
+    /// [`peek_actions`]: ./struct.Node.html#method.peek_actions
+    /// [`poll_action`]: ./struct.Node.html#method.poll_action
+    pub fn pending_action_count(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// Returns `true` if the number of queued actions exceeds [`NodeOptions::action_queue_high_water`].
+    ///
+    /// The caller can use this as a back-pressure signal, i.e., stop feeding input to the node
+    /// until the actions are drained.
+    ///
+    /// [`NodeOptions::action_queue_high_water`]: ./struct.NodeOptions.html#structfield.action_queue_high_water
+    pub fn is_overloaded(&self) -> bool {
+        self.options
+            .action_queue_high_water
+            .is_some_and(|mark| self.actions.len() > mark)
+    }
+
+    /// Returns an iterator over the actions queued in the node without consuming them.
+    ///
+    /// The actions are yielded in the order they will be returned by [`poll_action`].
+    ///
+    /// Note that `GraftMessage`s caused by expired `IHAVE` messages are enqueued
+    /// only when [`poll_action`] is called, so they are not included in the iterator beforehand.
+    ///
+    /// [`poll_action`]: ./struct.Node.html#method.poll_action
+    pub fn peek_actions(&self) -> vec_deque::Iter<'_, Action<T>> {
+        self.actions.iter()
+    }
+
+    /// Sends a `ProbeMessage` to the given peer and returns the nonce of the message.
+    ///
+    /// The peer will reply with a `ProbeAckMessage` which has the same nonce.
+    /// For example, by recording the time of the clock when calling this method,
+    /// the round-trip time to the peer can be measured when the reply is received.
+    ///
+    /// Probing does not affect the spanning tree.
+    ///
+    /// If the peer is not a neighbor of this node, this method will return `None`.
+    pub fn probe_peer(&mut self, peer: &T::NodeId) -> Option<u64> {
+        if !self.is_known_node(peer) {
+            return None;
+        }
+        let nonce = self.probe_seqno;
+        self.probe_seqno += 1;
+        let probe = ProbeMessage::new(&self.id, nonce);
+        self.actions.send(peer.clone(), probe);
+        Some(nonce)
+    }
+
+    /// Removes the queued `Send` actions destined for the given peer.
+    ///
+    /// The sends throttled by [`NodeOptions::per_peer_send_rate`] are also removed.
+    ///
+    /// This method returns the number of the removed actions.
+    ///
+    /// It is useful, for example, after calling [`handle_neighbor_down`].
+    ///
+    /// [`handle_neighbor_down`]: ./struct.Node.html#method.handle_neighbor_down
+    /// [`NodeOptions::per_peer_send_rate`]: ./struct.NodeOptions.html#structfield.per_peer_send_rate
+    pub fn drop_pending_sends_to(&mut self, peer: &T::NodeId) -> usize {
+        self.actions.remove_sends_to(peer)
+    }
+
+    /// Removes and returns the queued `Send` actions destined for the given peer.
+    ///
+    /// The other actions (including the `Deliver` actions) are left in the queue in order.
+    /// Like [`drain_actions`], expired `IHAVE` messages are handled before taking the actions.
+    /// Note that the sends throttled by [`NodeOptions::per_peer_send_rate`] are not returned
+    /// until they are released.
+    ///
+    /// [`drain_actions`]: ./struct.Node.html#method.drain_actions
+    /// [`NodeOptions::per_peer_send_rate`]: ./struct.NodeOptions.html#structfield.per_peer_send_rate
+    pub fn drain_actions_to(&mut self, peer: &T::NodeId) -> Vec<Action<T>> {
+        self.handle_expiration();
+        self.actions.take_sends_to(peer)
+    }
+
+    /// Handles the given incoming message.
+    ///
+    /// This method will return `false` if the sender of the message is not a neighbor of this node
+    /// or is this node itself (e.g., a looped back or spoofed message).
+    pub fn handle_protocol_message(&mut self, message: ProtocolMessage<T>) -> bool {
+        self.try_handle_protocol_message(message).is_ok()
+    }
+
+    /// Handles the given incoming message.
+    ///
+    /// This is the same as [`handle_protocol_message`],
+    /// but returns the reason if the message is rejected.
+    ///
+    /// [`handle_protocol_message`]: ./struct.Node.html#method.handle_protocol_message
+    pub fn try_handle_protocol_message(
+        &mut self,
+        message: ProtocolMessage<T>,
+    ) -> Result<(), HandleError> {
+        self.sync_clock();
+        self.check_incoming(&message)?;
+        self.dispatch(message);
+        Ok(())
+    }
+
+    /// Handles the given incoming message without consuming it.
     ///
-    /// This method will return `false` if the sender of the message is not a neighbor of this node.
-    pub fn handle_protocol_message(&mut self, message: ProtocolMessage<T>) -> bool {
-        if !self.is_known_node(message.sender()) {
+    /// This behaves the same as [`handle_protocol_message`],
+    /// but the message is cloned only if the node needs to keep (a part of) it.
+    /// For example, a duplicate `GossipMessage` is handled without cloning its payload.
+    ///
+    /// [`handle_protocol_message`]: ./struct.Node.html#method.handle_protocol_message
+    pub fn handle_protocol_message_ref(&mut self, message: &ProtocolMessage<T>) -> bool {
+        self.sync_clock();
+        if self.check_incoming(message).is_err() {
             return false;
         }
         match message {
-            ProtocolMessage::Gossip(m) => self.handle_gossip(m),
-            ProtocolMessage::Ihave(m) => self.handle_ihave(m),
-            ProtocolMessage::Graft(m) => self.handle_graft(m),
-            ProtocolMessage::Prune(m) => self.handle_prune(m),
+            ProtocolMessage::Gossip(m) if self.is_duplicate(&m.message.id) => {
+                self.handle_duplicate_gossip(&m.sender, &m.message, m.round);
+                true
+            }
+            _ => {
+                self.dispatch(message.clone());
+                true
+            }
         }
-        true
     }
 
     /// Accepts new neighbor.
@@ -201,11 +2121,37 @@ impl<T: System> Node<T> {
         if self.is_known_node(neighbor_node_id) || self.id == *neighbor_node_id {
             return;
         }
-        for message_id in self.messages.keys() {
-            let ihave = IhaveMessage::new(&self.id, message_id.clone(), 0, false);
+        for message_id in self.resync_message_ids() {
+            let ihave = IhaveMessage::new(&self.id, message_id, 0, false);
             self.actions.send(neighbor_node_id.clone(), ihave);
         }
-        self.eager_push_peers.insert(neighbor_node_id.clone());
+        self.add_neighbor(neighbor_node_id);
+    }
+
+    /// Accepts multiple new neighbors at once.
+    ///
+    /// This is equivalent to calling [`handle_neighbor_up`] for each peer,
+    /// but iterates over the messages kept by the node only once.
+    ///
+    /// [`handle_neighbor_up`]: ./struct.Node.html#method.handle_neighbor_up
+    pub fn handle_neighbor_up_many<I>(&mut self, peers: I)
+    where
+        I: IntoIterator<Item = T::NodeId>,
+    {
+        let mut new_peers = Vec::new();
+        for peer in peers {
+            if self.is_known_node(&peer) || self.id == peer {
+                continue;
+            }
+            self.add_neighbor(&peer);
+            new_peers.push(peer);
+        }
+        for message_id in self.resync_message_ids() {
+            let ihave = IhaveMessage::new(&self.id, message_id, 0, false);
+            for peer in &new_peers {
+                self.actions.send(peer.clone(), ihave.clone());
+            }
+        }
     }
 
     /// Removes downed neighbor.
@@ -213,8 +2159,7 @@ impl<T: System> Node<T> {
         if !self.is_known_node(neighbor_node_id) {
             return;
         }
-        self.eager_push_peers.remove(neighbor_node_id);
-        self.lazy_push_peers.remove(neighbor_node_id);
+        self.remove_peer(neighbor_node_id);
 
         if self.eager_push_peers.is_empty() {
             while let Some(ihave) = self.missings.pop_expired(&Clock::max()) {
@@ -225,6 +2170,27 @@ impl<T: System> Node<T> {
         }
     }
 
+    /// Applies the given event to the node and returns the resulting actions.
+    ///
+    /// The actions queued before calling this method are also included in the result.
+    /// Applying the same sequence of events to a fresh node yields the same sequence of actions
+    /// as long as the event handling does not depend on the iteration order of the peer sets.
+    pub fn apply_event(&mut self, event: NodeEvent<T>) -> Vec<Action<T>> {
+        match event {
+            NodeEvent::Inbound(m) => {
+                self.handle_protocol_message(m);
+            }
+            NodeEvent::NeighborUp(p) => self.handle_neighbor_up(&p),
+            NodeEvent::NeighborDown(p) => self.handle_neighbor_down(&p),
+            NodeEvent::Broadcast(m) => self.broadcast_message(m),
+            NodeEvent::Tick(d) => self.clock.tick(d),
+            NodeEvent::Forget(id) => {
+                self.forget_message(&id);
+            }
+        }
+        self.drain_actions().collect()
+    }
+
     /// Returns a reference to the clock of the node.
     pub fn clock(&self) -> &Clock {
         &self.clock
@@ -240,17 +2206,114 @@ impl<T: System> Node<T> {
         &mut self.clock
     }
 
-    /// Returns the nearest time when the timeout of a `IHAVE` message expires.
+    /// Returns the nearest time when the timeout of a `IHAVE` message expires
+    /// or a delayed `IHAVE` message (see [`NodeOptions::lazy_suppression_window`]) should be sent.
+    ///
+    /// This also takes into account the time when the messages throttled by
+    /// [`NodeOptions::per_peer_send_rate`] are released.
     ///
     /// If the node has no `IHAVE` messages to be handled, this method will return `None`.
+    ///
+    /// [`NodeOptions::lazy_suppression_window`]: ./struct.NodeOptions.html#structfield.lazy_suppression_window
+    /// [`NodeOptions::per_peer_send_rate`]: ./struct.NodeOptions.html#structfield.per_peer_send_rate
     pub fn next_expiry_time(&self) -> Option<NodeTime> {
-        self.missings.next_expiry_time()
+        [
+            self.missings.next_expiry_time(),
+            self.delayed_ihaves.next_send_time(),
+            self.outstanding_grafts.next_deadline(),
+            self.actions.next_release_time(),
+        ]
+        .iter()
+        .flatten()
+        .min()
+        .copied()
+    }
+
+    /// Returns the duration until [`next_expiry_time`].
+    ///
+    /// If the time has already passed, this method will return `Duration::from_millis(0)`.
+    /// If the node has no `IHAVE` messages to be handled, this method will return `None`.
+    ///
+    /// [`next_expiry_time`]: ./struct.Node.html#method.next_expiry_time
+    pub fn time_until_next_expiry(&self) -> Option<Duration> {
+        self.next_expiry_time().map(|t| {
+            t.as_duration()
+                .saturating_sub(self.clock.now().as_duration())
+        })
+    }
+
+    /// Returns `true` if the node has no work to do, otherwise `false`.
+    ///
+    /// The node is idle if there are no queued actions (including the throttled sends) and no pending expirations
+    /// (i.e., no waiting `IHAVE` messages, no delayed `IHAVE` messages and no outstanding `GRAFT` messages).
+    ///
+    /// Note that [`next_expiry_time`] may still return `Some(_)` for an idle node,
+    /// because the timeouts of the already received messages are discarded lazily.
+    ///
+    /// [`next_expiry_time`]: ./struct.Node.html#method.next_expiry_time
+    pub fn is_idle(&self) -> bool {
+        self.actions.len() == 0
+            && self.actions.throttled_len() == 0
+            && self.missings.waiting_messages() == 0
+            && self.delayed_ihaves.next_send_time().is_none()
+            && self.outstanding_grafts.next_deadline().is_none()
     }
 
     fn handle_expiration(&mut self) {
-        while let Some(ihave) = self.missings.pop_expired(&self.clock) {
-            self.send_graft(ihave);
+        self.sync_clock();
+        self.actions
+            .advance(self.clock.now(), self.options.per_peer_send_rate);
+
+        let now = self.clock.now();
+        self.graft_stats.timed_out += self.graft_deadlines.pop_expired(now);
+        while let Some((destination, ihave)) = self.delayed_ihaves.pop_due(&self.clock) {
+            if self.lazy_push_peers.contains(&destination) {
+                self.actions.send(destination, ihave);
+            }
+        }
+
+        let now = self.clock.now();
+        let owner_limit = self.options.max_graft_owners_per_message;
+        let max_grafts = self
+            .options
+            .max_grafts_per_cycle
+            .map_or(usize::MAX, |n| n.max(1));
+        let mut grafts = 0;
+        while grafts < max_grafts {
+            let (eager_push_peers, lazy_push_peers) =
+                (&self.eager_push_peers, &self.lazy_push_peers);
+            let is_known =
+                |n: &T::NodeId| eager_push_peers.contains(n) || lazy_push_peers.contains(n);
+            let ihave = if let Some(ihave) = owner_limit
+                .and_then(|limit| self.outstanding_grafts.pop_ready(now, limit, is_known))
+            {
+                ihave
+            } else if let Some(ihave) = self.missings.pop_expired(&self.clock) {
+                ihave
+            } else {
+                break;
+            };
+
+            if let Some(limit) = owner_limit {
+                if self
+                    .outstanding_grafts
+                    .is_full(&ihave.message_id, now, limit, is_known)
+                {
+                    self.outstanding_grafts.defer(ihave);
+                    continue;
+                }
+            }
+
+            let (message_id, owner) = (ihave.message_id.clone(), ihave.sender.clone());
+            if self.send_graft(ihave) {
+                grafts += 1;
+                if owner_limit.is_some() {
+                    let deadline = now + self.options.ihave_timeout;
+                    self.outstanding_grafts.record(message_id, owner, deadline);
+                }
+            }
         }
+        self.outstanding_grafts.sweep(now);
     }
 
     fn send_graft(&mut self, ihave: IhaveMessage<T>) -> bool {
@@ -258,8 +2321,21 @@ impl<T: System> Node<T> {
             // The node has been removed from neighbors
             false
         } else {
-            self.eager_push_peers.insert(ihave.sender.clone());
-            self.lazy_push_peers.remove(&ihave.sender);
+            if !self.options.adaptive_promotion {
+                self.make_eager(&ihave.sender);
+            }
+            self.pruned_by.remove(&ihave.sender);
+            self.graft_stats.sent += 1;
+            let deadline = self.clock.now() + self.options.ihave_timeout;
+            let replaced = self.graft_deadlines.insert(
+                ihave.message_id.clone(),
+                ihave.sender.clone(),
+                deadline,
+            );
+            if replaced {
+                // The previous request to the same peer is superseded
+                self.graft_stats.timed_out += 1;
+            }
             self.actions.send(
                 ihave.sender,
                 GraftMessage::new(&self.id, Some(ihave.message_id), ihave.round),
@@ -268,81 +2344,444 @@ impl<T: System> Node<T> {
         }
     }
 
-    #[allow(clippy::map_entry)]
-    fn handle_gossip(&mut self, gossip: GossipMessage<T>) {
-        if self.messages.contains_key(&gossip.message.id) {
-            self.eager_push_peers.remove(&gossip.sender);
-            self.lazy_push_peers.insert(gossip.sender.clone());
-            self.actions
-                .send(gossip.sender, PruneMessage::new(&self.id));
-        } else {
-            self.actions.deliver(gossip.message.clone());
+    fn is_duplicate(&self, message_id: &T::MessageId) -> bool {
+        self.messages.contains_key(message_id) || self.forgottens.contains(message_id)
+    }
 
-            self.eager_push(&gossip);
-            self.lazy_push(&gossip);
-            self.eager_push_peers.insert(gossip.sender.clone());
-            self.lazy_push_peers.remove(&gossip.sender);
+    fn handle_duplicate_gossip(&mut self, sender: &T::NodeId, message: &Message<T>, round: u16) {
+        if let Some(p) = self.provenances.get_mut(&message.id) {
+            if p.nearest_sender.as_ref().is_some_and(|x| round < x.1) {
+                p.nearest_sender = Some((sender.clone(), round));
+            }
+        }
+        self.delayed_ihaves.suppress(sender, &message.id);
+        self.pruned_by.remove(sender);
+        if let Some(stored) = self.messages.get(&message.id) {
+            if let Some(eq) = self.payload_eq {
+                if !eq(&self.transform.load(stored), &message.payload) {
+                    self.payload_conflict_count += 1;
+                }
+            }
+            self.acknowledge(&message.id, sender);
+            self.suggest_forget_if_acknowledged(&message.id);
+        }
+
+        let tolerance = self.options.duplicate_tolerance;
+        if tolerance > 0 {
+            let count = self.duplicate_counts.entry(sender.clone()).or_insert(0);
+            *count += 1;
+            if *count <= tolerance {
+                return;
+            }
+            self.duplicate_counts.remove(sender);
+        }
+
+        let mut prune_target = sender;
+        if let Some(first_sender) = self.message_senders.get(&message.id) {
+            let replace = first_sender != prune_target
+                && self.eager_push_peers.contains(first_sender)
+                && self.peer_score(first_sender) < self.peer_score(prune_target);
+            if replace {
+                // Keeps the more reliable link in the eager push peers
+                prune_target = first_sender;
+            }
+        }
+        if !self.can_demote(prune_target) || !self.policy.should_prune(prune_target, round) {
+            return;
+        }
+        let prune_target = prune_target.clone();
+        self.make_lazy(&prune_target);
+        self.actions.send(prune_target, PruneMessage::new(&self.id));
+    }
+
+    fn dispatch(&mut self, message: ProtocolMessage<T>) {
+        match message {
+            ProtocolMessage::Gossip(m) => self.handle_gossip(m),
+            ProtocolMessage::Ihave(m) => self.handle_ihave(m),
+            ProtocolMessage::IhaveMany(m) => {
+                for ihave in m.into_ihaves() {
+                    self.handle_ihave(ihave);
+                }
+            }
+            ProtocolMessage::Graft(m) => self.handle_graft(m),
+            ProtocolMessage::Prune(m) => self.handle_prune(m),
+            ProtocolMessage::GraftNack(m) => self.handle_graft_nack(m),
+            ProtocolMessage::Probe(m) => {
+                let ack = ProbeAckMessage::new(&self.id, m.nonce);
+                self.actions.send(m.sender, ack);
+            }
+            ProtocolMessage::ProbeAck(_) => {}
+        }
+    }
+
+    fn handle_gossip(&mut self, gossip: GossipMessage<T>) {
+        if self.is_duplicate(&gossip.message.id) {
+            self.handle_duplicate_gossip(&gossip.sender, &gossip.message, gossip.round);
+            return;
+        }
 
-            self.optimize(&gossip);
+        self.delayed_ihaves
+            .suppress(&gossip.sender, &gossip.message.id);
+        self.pruned_by.remove(&gossip.sender);
+        if self.is_oversized(&gossip.message.payload) {
+            self.rejected_payload_count += 1;
+            self.record_drop(
+                DropReason::OversizedPayload,
+                Some(&gossip.sender),
+                Some(&gossip.message.id),
+            );
             self.missings.remove(&gossip.message.id);
-            self.messages
-                .insert(gossip.message.id, gossip.message.payload);
+            self.outstanding_grafts.remove(&gossip.message.id);
+            return;
+        }
+
+        let acknowledger = Some(&gossip.sender)
+            .filter(|_| self.options.suggest_forget)
+            .cloned();
+        let deliver_first = self.options.delivery_order == DeliveryOrder::BeforeForward;
+        if deliver_first {
+            self.deliver(gossip.message.clone(), gossip.round);
+        }
+
+        self.duplicate_counts.remove(&gossip.sender);
+        self.start_provenance(&gossip.message.id, Some((&gossip.sender, gossip.round)));
+        self.eager_push(&gossip);
+        self.lazy_push(&gossip);
+        self.make_eager(&gossip.sender);
+
+        self.optimize(&gossip);
+        if !deliver_first {
+            self.deliver(gossip.message.clone(), gossip.round);
+        }
+        let (satisfied, timed_out) =
+            self.graft_deadlines
+                .resolve(&gossip.message.id, &gossip.sender, self.clock.now());
+        self.graft_stats.satisfied += satisfied;
+        self.graft_stats.timed_out += timed_out;
+        self.missings.remove(&gossip.message.id);
+        self.outstanding_grafts.remove(&gossip.message.id);
+        self.message_senders
+            .insert(gossip.message.id.clone(), gossip.sender);
+        self.message_rounds
+            .insert(gossip.message.id.clone(), gossip.round);
+        let payload = self.transform.store(gossip.message.payload);
+        self.store_message(gossip.message.id.clone(), payload);
+        if let Some(sender) = acknowledger {
+            self.acknowledge(&gossip.message.id, &sender);
+            self.suggest_forget_if_acknowledged(&gossip.message.id);
         }
     }
 
     fn handle_ihave(&mut self, mut ihave: IhaveMessage<T>) {
+        self.delayed_ihaves
+            .suppress(&ihave.sender, &ihave.message_id);
         if self.messages.contains_key(&ihave.message_id) {
+            self.acknowledge(&ihave.message_id, &ihave.sender);
+            self.suggest_forget_if_acknowledged(&ihave.message_id);
+            return;
+        }
+        if self.forgottens.contains(&ihave.message_id) {
             return;
         }
+        if let Some(max) = self.options.max_waiting_messages {
+            if self.missings.waiting_messages() >= max && !self.missings.contains(&ihave.message_id)
+            {
+                self.dropped_ihave_count += 1;
+                self.record_drop(
+                    DropReason::TooManyWaitingMessages,
+                    Some(&ihave.sender),
+                    Some(&ihave.message_id),
+                );
+                return;
+            }
+        }
         if self.eager_push_peers.is_empty() {
             ihave.realtime = true;
         }
-        self.missings
-            .push(ihave, &self.clock, self.options.ihave_timeout);
+        self.missings.push(
+            ihave,
+            &self.clock,
+            self.options.ihave_timeout,
+            self.options.realtime_ihave_timeout,
+            self.options.max_backup_owners.unwrap_or(usize::MAX),
+        );
     }
 
     fn handle_graft(&mut self, mut graft: GraftMessage<T>) {
-        self.eager_push_peers.insert(graft.sender.clone());
-        self.lazy_push_peers.remove(&graft.sender);
+        if graft
+            .message_id
+            .as_ref()
+            .is_some_and(|id| self.retracted.contains(id))
+        {
+            self.actions.send(graft.sender, PruneMessage::new(&self.id));
+            return;
+        }
+        if !self.is_distant_graft(&graft) && !self.is_flapping(&graft.sender) {
+            self.make_eager(&graft.sender);
+        }
         if let Some(message_id) = graft.message_id.take() {
-            if let Some(payload) = self.messages.get(&message_id).cloned() {
-                let gossip =
-                    GossipMessage::new(&self.id, Message::new(message_id, payload), graft.round);
+            if let Some(payload) = self
+                .messages
+                .get(&message_id)
+                .map(|p| self.transform.load(p))
+            {
+                // Uses the actual hop count of the message from the source rather than the requested one
+                let round = self
+                    .message_rounds
+                    .get(&message_id)
+                    .map_or(graft.round, |r| r.saturating_add(1));
+                let gossip = GossipMessage::new(&self.id, Message::new(message_id, payload), round);
+                if let Some(p) = self.provenances.get_mut(&gossip.message.id) {
+                    p.forwarded_to.push(graft.sender.clone());
+                }
+                self.acknowledge(&gossip.message.id, &graft.sender);
+                self.suggest_forget_if_acknowledged(&gossip.message.id);
                 self.actions.send(graft.sender, gossip);
+            } else {
+                let unknown =
+                    !self.is_duplicate(&message_id) && !self.missings.contains(&message_id);
+                let nack = GraftNackMessage::new(&self.id, message_id.clone());
+                self.actions.send(graft.sender.clone(), nack);
+                if unknown && self.options.reciprocal_graft {
+                    // The sender presumably knows the message, so waits for it as if an `IHAVE` was received
+                    let ihave =
+                        IhaveMessage::new(&graft.sender, message_id.clone(), graft.round, false);
+                    self.missings.push(
+                        ihave,
+                        &self.clock,
+                        self.options.ihave_timeout,
+                        self.options.realtime_ihave_timeout,
+                        self.options.max_backup_owners.unwrap_or(usize::MAX),
+                    );
+                    if let Some(ihave) = self.missings.pop_owner(&message_id) {
+                        self.send_graft(ihave);
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_graft_nack(&mut self, nack: GraftNackMessage<T>) {
+        if self.is_duplicate(&nack.message_id) {
+            return;
+        }
+        if !self.graft_deadlines.remove(&nack.message_id, &nack.sender) {
+            // Ignores the unsolicited (or too late) nack
+            return;
+        }
+        self.graft_stats.nacked += 1;
+        self.outstanding_grafts
+            .remove_outstanding(&nack.message_id, &nack.sender);
+
+        // Requests the message to the next owner immediately
+        let deadline = self.clock.now() + self.options.ihave_timeout;
+        while let Some(ihave) = self
+            .outstanding_grafts
+            .pop_deferred(&nack.message_id)
+            .or_else(|| self.missings.pop_owner(&nack.message_id))
+        {
+            let owner = ihave.sender.clone();
+            if self.send_graft(ihave) {
+                if self.options.max_graft_owners_per_message.is_some() {
+                    self.outstanding_grafts
+                        .record(nack.message_id, owner, deadline);
+                }
+                break;
             }
         }
     }
 
     fn handle_prune(&mut self, prune: PruneMessage<T>) {
-        self.eager_push_peers.remove(&prune.sender);
-        self.lazy_push_peers.insert(prune.sender);
+        self.pruned_by.insert(prune.sender.clone());
+        if !self.can_demote(&prune.sender) || self.is_flapping(&prune.sender) {
+            return;
+        }
+        self.make_lazy(&prune.sender);
     }
 
     fn eager_push(&mut self, gossip: &GossipMessage<T>) {
         let round = gossip.round.saturating_add(1);
-        for peer in self
+        let mut peers = self
             .eager_push_peers
             .iter()
             .filter(|n| **n != gossip.sender)
-        {
+            .collect::<Vec<_>>();
+        if self.options.shuffle_eager_order {
+            // Fisher-Yates shuffle
+            for i in (1..peers.len()).rev() {
+                let j = (self.rng.next_u64() % (i + 1) as u64) as usize;
+                peers.swap(i, j);
+            }
+        }
+        for peer in peers {
             let forward = GossipMessage::new(&self.id, gossip.message.clone(), round);
             self.actions.send(peer.clone(), forward);
+            if let Some(p) = self.provenances.get_mut(&gossip.message.id) {
+                p.forwarded_to.push(peer.clone());
+            }
+            self.eagerly_forwarded.insert(gossip.message.id.clone());
+            if self.options.suggest_forget {
+                self.acknowledgements
+                    .entry(gossip.message.id.clone())
+                    .or_default()
+                    .peers
+                    .insert(peer.clone());
+            }
+        }
+    }
+
+    fn is_distant_graft(&self, graft: &GraftMessage<T>) -> bool {
+        let gap = match self.options.max_graft_round_gap {
+            None => return false,
+            Some(gap) => gap,
+        };
+        graft
+            .message_id
+            .as_ref()
+            .and_then(|id| self.message_rounds.get(id))
+            .is_some_and(|r| graft.round > r.saturating_add(1).saturating_add(gap))
+    }
+
+    fn flood(&mut self, message: Message<T>, round: u16) {
+        let deliver_first = self.options.delivery_order == DeliveryOrder::BeforeForward;
+        if deliver_first {
+            self.deliver(message.clone(), round);
+        }
+
+        let gossip = GossipMessage::new(&self.id, message, round);
+        self.start_provenance(&gossip.message.id, None);
+        self.eager_push(&gossip);
+        self.lazy_push(&gossip);
+        if !deliver_first {
+            self.deliver(gossip.message.clone(), round);
+        }
+        self.message_rounds.insert(gossip.message.id.clone(), round);
+        let payload = self.transform.store(gossip.message.payload);
+        self.store_message(gossip.message.id.clone(), payload);
+        self.suggest_forget_if_acknowledged(&gossip.message.id);
+    }
+
+    fn is_oversized(&self, payload: &T::MessagePayload) -> bool {
+        self.options
+            .max_payload_size
+            .is_some_and(|max| (self.payload_size)(payload) > max)
+    }
+
+    fn deliver(&mut self, message: Message<T>, round: u16) {
+        if self.options.dedup_delivers && self.messages.contains_key(&message.id) {
+            return;
+        }
+        self.actions.deliver(message);
+        if self.options.track_round_histogram {
+            *self.round_histogram.entry(round).or_insert(0) += 1;
+        }
+
+        let window = self.options.delivery_rate_window;
+        if window != Duration::from_millis(0) {
+            let now = self.clock.now();
+            while self
+                .delivery_times
+                .front()
+                .is_some_and(|&t| self.clock.elapsed_since(t) >= window)
+            {
+                self.delivery_times.pop_front();
+            }
+            self.delivery_times.push_back(now);
+        }
+    }
+
+    fn acknowledge(&mut self, message_id: &T::MessageId, peer: &T::NodeId) {
+        if self.options.suggest_forget {
+            self.acknowledgements
+                .entry(message_id.clone())
+                .or_default()
+                .peers
+                .insert(peer.clone());
+        }
+    }
+
+    fn suggest_forget_if_acknowledged(&mut self, message_id: &T::MessageId) {
+        if !self.options.suggest_forget || !self.messages.contains_key(message_id) {
+            return;
+        }
+        let ack = self.acknowledgements.entry(message_id.clone()).or_default();
+        if !ack.suggested
+            && self
+                .eager_push_peers
+                .iter()
+                .chain(self.lazy_push_peers.iter())
+                .all(|p| ack.peers.contains(p))
+        {
+            ack.suggested = true;
+            self.actions.suggest_forget(message_id.clone());
+        }
+    }
+
+    fn start_provenance(&mut self, message_id: &T::MessageId, sender: Option<(&T::NodeId, u16)>) {
+        if self.options.track_provenance {
+            let provenance = MessageProvenance {
+                received_from: sender.map(|(s, _)| s.clone()),
+                forwarded_to: Vec::new(),
+                nearest_sender: sender.map(|(s, r)| (s.clone(), r)),
+            };
+            self.provenances.insert(message_id.clone(), provenance);
         }
     }
 
     fn lazy_push(&mut self, gossip: &GossipMessage<T>) {
         let round = gossip.round.saturating_add(1);
         let ihave = IhaveMessage::new(&self.id, gossip.message.id.clone(), round, true);
-        for peer in self.lazy_push_peers.iter().filter(|n| **n != gossip.sender) {
-            self.actions.send(peer.clone(), ihave.clone());
+        let window = self.options.lazy_suppression_window;
+        let mut peers = self
+            .lazy_push_peers
+            .iter()
+            .filter(|n| **n != gossip.sender)
+            .collect::<Vec<_>>();
+        if let Some(fanout) = self.options.lazy_fanout {
+            // Partial Fisher-Yates shuffle
+            for i in 0..fanout.min(peers.len()) {
+                let j = i + (self.rng.next_u64() % (peers.len() - i) as u64) as usize;
+                peers.swap(i, j);
+            }
+            peers.truncate(fanout);
+        }
+        for peer in peers {
+            // The peer sets are disjoint, so no peer receives both GOSSIP and IHAVE in the same step
+            debug_assert!(!self.eager_push_peers.contains(peer));
+            if window == Duration::from_millis(0) {
+                self.actions.send(peer.clone(), ihave.clone());
+            } else {
+                // An overflowing send time holds the IHAVE until it is suppressed or forgotten
+                let send_time = self
+                    .clock
+                    .now()
+                    .checked_add(window)
+                    .unwrap_or_else(|| NodeTime::from_duration(Duration::MAX));
+                self.delayed_ihaves
+                    .push(peer.clone(), ihave.clone(), send_time);
+            }
         }
     }
 
     fn optimize(&mut self, gossip: &GossipMessage<T>) {
+        if !self.options.optimization_enabled {
+            return;
+        }
         if let Some((ihave_round, ihave_owner)) = self.missings.get_ihave(&gossip.message.id) {
-            let optimize =
-                gossip.round.checked_sub(ihave_round) >= Some(self.options.optimization_threshold);
+            // Rounds saturate instead of wrapping (see `eager_push` and `lazy_push`).
+            // If the `GossipMessage` has a round not greater than the `IhaveMessage`,
+            // the current tree path is already the shortest one known, so nothing is optimized.
+            // Note that the threshold is always non-zero.
+            let gap = gossip.round.saturating_sub(ihave_round);
+            let optimize = gap >= self.options.optimization_threshold.get()
+                && self.peer_score(ihave_owner) >= self.peer_score(&gossip.sender)
+                && self.can_demote(&gossip.sender)
+                && self.policy.should_optimize(ihave_round, gossip.round);
             if optimize {
+                self.optimization_count += 1;
+                if self.options.optimization_dry_run {
+                    return;
+                }
                 let graft = GraftMessage::new(&self.id, None, ihave_round);
                 let prune = PruneMessage::new(&self.id);
                 self.actions.send(ihave_owner.clone(), graft);
@@ -351,7 +2790,296 @@ impl<T: System> Node<T> {
         }
     }
 
+    fn add_neighbor(&mut self, peer: &T::NodeId) {
+        self.removed_peers.remove(peer);
+        if self.options.adaptive_promotion {
+            self.make_lazy(peer);
+            return;
+        }
+        match self.options.new_neighbor_mode {
+            NewNeighborMode::Eager => self.make_eager(peer),
+            NewNeighborMode::Lazy => self.make_lazy(peer),
+        }
+    }
+
+    fn make_eager(&mut self, peer: &T::NodeId) {
+        if self.options.pull_only {
+            self.make_lazy(peer);
+            return;
+        }
+        if self.lazy_push_peers.remove(peer) {
+            self.record_peer_change(|| PeerChange::LazyRemoved(peer.clone()));
+            self.record_peer_move(peer);
+        }
+        if self.eager_push_peers.insert(peer.clone()) {
+            self.record_peer_change(|| PeerChange::EagerAdded(peer.clone()));
+        }
+    }
+
+    fn make_lazy(&mut self, peer: &T::NodeId) {
+        if self.eager_push_peers.remove(peer) {
+            self.record_peer_change(|| PeerChange::EagerRemoved(peer.clone()));
+            self.record_peer_move(peer);
+        }
+        if self.lazy_push_peers.insert(peer.clone()) {
+            self.record_peer_change(|| PeerChange::LazyAdded(peer.clone()));
+        }
+    }
+
+    fn remove_peer(&mut self, peer: &T::NodeId) {
+        if self.eager_push_peers.remove(peer) {
+            self.record_peer_change(|| PeerChange::EagerRemoved(peer.clone()));
+        }
+        if self.lazy_push_peers.remove(peer) {
+            self.record_peer_change(|| PeerChange::LazyRemoved(peer.clone()));
+        }
+        self.duplicate_counts.remove(peer);
+        self.pruned_by.remove(peer);
+        self.peer_moves.remove(peer);
+        let capacity = self.options.removed_peer_memory;
+        self.removed_peers.push(peer.clone(), capacity);
+    }
+
+    fn record_peer_change<F>(&mut self, f: F)
+    where
+        F: FnOnce() -> PeerChange<T>,
+    {
+        if self.options.track_peer_changes {
+            self.peer_changes.push(f());
+        }
+    }
+
+    fn record_peer_move(&mut self, peer: &T::NodeId) {
+        if self.options.peer_flap_cooldown != Duration::from_millis(0) {
+            self.peer_moves.insert(peer.clone(), self.clock.now());
+        }
+    }
+
+    fn is_flapping(&self, peer: &T::NodeId) -> bool {
+        let cooldown = self.options.peer_flap_cooldown;
+        self.peer_moves
+            .get(peer)
+            .is_some_and(|&t| match t.checked_add(cooldown) {
+                None => true,
+                Some(end) => self.clock.now() < end,
+            })
+    }
+
+    fn can_demote(&self, peer: &T::NodeId) -> bool {
+        !self.eager_push_peers.contains(peer)
+            || self.eager_push_peers.len() > self.options.min_eager_peers
+    }
+
+    fn sync_clock(&mut self) {
+        if let Some(source) = self.time_source.as_ref() {
+            self.clock.tick_to(source.now());
+        }
+    }
+
+    fn store_message(&mut self, message_id: T::MessageId, payload: T::MessagePayload) {
+        self.message_seqnos
+            .insert(message_id.clone(), self.message_seqno);
+        self.message_seqno += 1;
+        self.received_times
+            .insert(message_id.clone(), self.clock.now());
+        self.retracted.remove(&message_id);
+        self.messages.insert(message_id, payload);
+    }
+
+    fn stored_message_ids(&self, order: ResyncOrder) -> Vec<T::MessageId> {
+        let mut ids = self
+            .message_seqnos
+            .iter()
+            .map(|(id, seqno)| (*seqno, id.clone()))
+            .collect::<Vec<_>>();
+        ids.sort_by_key(|x| x.0);
+        if order == ResyncOrder::NewestFirst {
+            ids.reverse();
+        }
+        ids.into_iter().map(|x| x.1).collect()
+    }
+
+    fn resync_message_ids(&self) -> Vec<T::MessageId> {
+        let mut ids = self.stored_message_ids(ResyncOrder::OldestFirst);
+        if let Some(limit) = self.options.resync_recent_only {
+            ids.drain(..ids.len().saturating_sub(limit));
+        }
+        if self.options.resync_order == ResyncOrder::NewestFirst {
+            ids.reverse();
+        }
+        ids
+    }
+
+    fn check_incoming(&mut self, message: &ProtocolMessage<T>) -> Result<(), HandleError> {
+        let verified = match self.verifier {
+            None => true,
+            Some(ref v) => v.verify(message),
+        };
+        let result = if verified {
+            self.check_sender(message.sender())
+        } else {
+            self.unverified_message_count += 1;
+            Err(HandleError::Unverified)
+        };
+        if let Err(e) = result {
+            self.record_drop(
+                DropReason::Rejected(e),
+                Some(message.sender()),
+                message.message_id(),
+            );
+        }
+        result
+    }
+
+    fn record_drop(
+        &mut self,
+        reason: DropReason,
+        sender: Option<&T::NodeId>,
+        message_id: Option<&T::MessageId>,
+    ) {
+        if self.options.drop_history == 0 {
+            return;
+        }
+        if self.drops.len() == self.options.drop_history {
+            self.drops.pop_front();
+        }
+        self.drops.push_back(DropEvent {
+            reason,
+            sender: sender.cloned(),
+            message_id: message_id.cloned(),
+        });
+    }
+
+    fn check_sender(&self, sender: &T::NodeId) -> Result<(), HandleError> {
+        if *sender == self.id {
+            Err(HandleError::OwnMessage)
+        } else if self.is_known_node(sender) {
+            Ok(())
+        } else if self.removed_peers.contains(sender) {
+            Err(HandleError::RemovedSender)
+        } else {
+            Err(HandleError::UnknownSender)
+        }
+    }
+
     fn is_known_node(&self, node_id: &T::NodeId) -> bool {
         self.eager_push_peers.contains(node_id) || self.lazy_push_peers.contains(node_id)
     }
 }
+#[cfg(any(test, feature = "test-util"))]
+impl<T: System> Node<T>
+where
+    T::NodeId: fmt::Debug,
+    T::MessageId: fmt::Debug,
+{
+    /// Checks the consistency of the internal state of the node.
+    ///
+    /// If an inconsistency is detected, this method will return an error describing it.
+    ///
+    /// This is intended for testing and is available only if the `test-util` feature is enabled.
+    pub fn validate_invariants(&self) -> Result<(), String> {
+        if self.is_known_node(&self.id) {
+            return Err(format!("The node {:?} is a peer of itself", self.id));
+        }
+        if let Some(peer) = self
+            .eager_push_peers
+            .iter()
+            .find(|n| self.lazy_push_peers.contains(n))
+        {
+            return Err(format!(
+                "The peer {:?} is both an eager and lazy push peer",
+                peer
+            ));
+        }
+        if let Some(message_id) = self.messages.keys().find(|id| self.missings.contains(id)) {
+            return Err(format!(
+                "The message {:?} is kept but still waited",
+                message_id
+            ));
+        }
+        if let Some(message_id) = self
+            .messages
+            .keys()
+            .find(|id| !self.message_seqnos.contains_key(id))
+        {
+            return Err(format!(
+                "The message {:?} has no sequence number",
+                message_id
+            ));
+        }
+        self.missings.validate()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn lazy_push_peers_mut(&mut self) -> &mut HashSet<T::NodeId> {
+        &mut self.lazy_push_peers
+    }
+}
+impl<'a, T: System> IntoIterator for &'a mut Node<T> {
+    type Item = Action<T>;
+    type IntoIter = DrainActions<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.drain_actions()
+    }
+}
+impl<T: System> Node<T>
+where
+    T::MessagePayload: PartialEq,
+{
+    /// Enables or disables the detection of payload conflicts.
+    ///
+    /// If enabled, each time the node receives a duplicate `GossipMessage`,
+    /// its payload is compared with the kept one, and [`payload_conflict_count`] is incremented
+    /// if they differ.
+    ///
+    /// This is disabled by default.
+    ///
+    /// [`payload_conflict_count`]: ./struct.Node.html#method.payload_conflict_count
+    pub fn detect_payload_conflicts(&mut self, enabled: bool) {
+        self.payload_eq = if enabled { Some(|a, b| a == b) } else { None };
+    }
+
+    /// Returns `true` if this node and `other` keep the same set of messages, otherwise `false`.
+    ///
+    /// The payloads are compared after being restored by the transforms of the respective nodes.
+    pub fn messages_eq(&self, other: &Node<T>) -> bool {
+        self.messages.len() == other.messages.len()
+            && self.messages.iter().all(|(id, payload)| {
+                other
+                    .messages
+                    .get(id)
+                    .is_some_and(|p| self.transform.load(payload) == other.transform.load(p))
+            })
+    }
+}
+impl<T: System> Node<T>
+where
+    T::MessageId: Ord,
+{
+    /// Forgets all messages whose identifiers are less than `watermark`.
+    ///
+    /// Each kept message is forgotten in the same way as [`forget_message`].
+    /// The `IHAVE` messages and the `GRAFT` requests associated with such identifiers are also discarded.
+    ///
+    /// This method returns the number of the forgotten messages kept by the node.
+    ///
+    /// [`forget_message`]: ./struct.Node.html#method.forget_message
+    pub fn forget_messages_before(&mut self, watermark: &T::MessageId) -> usize {
+        let ids = self
+            .messages
+            .keys()
+            .filter(|id| *id < watermark)
+            .cloned()
+            .collect::<Vec<_>>();
+        for id in &ids {
+            self.forget_message(id);
+        }
+        self.retracted.retain(|id| id >= watermark);
+        self.missings.retain(|id| id >= watermark);
+        self.delayed_ihaves.retain(|id| id >= watermark);
+        self.outstanding_grafts.retain(|id| id >= watermark);
+        self.graft_deadlines.retain(|id| id >= watermark);
+        ids.len()
+    }
+}
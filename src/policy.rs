@@ -0,0 +1,36 @@
+use crate::System;
+
+/// This trait allows for customizing the reconfiguration decisions of the spanning tree made by Plumtree [Node].
+///
+/// The node consults the policy after the decisions made according to [NodeOptions]
+/// (e.g., [`NodeOptions::duplicate_tolerance`] and [`NodeOptions::optimization_threshold`]),
+/// so a policy can veto a reconfiguration but cannot force one.
+///
+/// All the methods return `true` by default, which corresponds to the behavior described in the paper.
+///
+/// [Node]: ./struct.Node.html
+/// [NodeOptions]: ./struct.NodeOptions.html
+/// [`NodeOptions::duplicate_tolerance`]: ./struct.NodeOptions.html#structfield.duplicate_tolerance
+/// [`NodeOptions::optimization_threshold`]: ./struct.NodeOptions.html#structfield.optimization_threshold
+pub trait TreePolicy<T: System> {
+    /// Returns `true` if the node should prune `peer` which has sent a duplicate `GossipMessage`
+    /// with the given round.
+    fn should_prune(&self, peer: &T::NodeId, round: u16) -> bool {
+        let _ = (peer, round);
+        true
+    }
+
+    /// Returns `true` if the node should optimize the spanning tree by grafting the owner of
+    /// an `IhaveMessage` with `candidate_round` and pruning the sender of a `GossipMessage` with `current_round`.
+    fn should_optimize(&self, candidate_round: u16, current_round: u16) -> bool {
+        let _ = (candidate_round, current_round);
+        true
+    }
+}
+
+/// The default [TreePolicy] which follows the behavior described in the paper.
+///
+/// [TreePolicy]: ./trait.TreePolicy.html
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultTreePolicy;
+impl<T: System> TreePolicy<T> for DefaultTreePolicy {}
@@ -0,0 +1,62 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::hash::Hash;
+
+/// A bounded set which remembers the most recently pushed identifiers.
+pub struct RecentIds<K> {
+    queue: VecDeque<K>,
+    ids: HashSet<K>,
+}
+impl<K: fmt::Debug> fmt::Debug for RecentIds<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RecentIds {{ queue: {:?} }}", self.queue)
+    }
+}
+impl<K: Clone + Hash + Eq> RecentIds<K> {
+    pub fn new() -> Self {
+        RecentIds {
+            queue: VecDeque::new(),
+            ids: HashSet::new(),
+        }
+    }
+
+    pub fn push(&mut self, id: K, capacity: usize) {
+        if !self.ids.contains(&id) {
+            self.ids.insert(id.clone());
+            self.queue.push_back(id);
+        }
+        while self.queue.len() > capacity {
+            let id = self.queue.pop_front().expect("never fails");
+            self.ids.remove(&id);
+        }
+    }
+
+    pub fn contains(&self, id: &K) -> bool {
+        self.ids.contains(id)
+    }
+
+    pub fn remove(&mut self, id: &K) {
+        if self.ids.remove(id) {
+            self.queue.retain(|x| x != id);
+        }
+    }
+
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let ids = &mut self.ids;
+        self.queue.retain(|id| {
+            let keep = f(id);
+            if !keep {
+                ids.remove(id);
+            }
+            keep
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.ids.clear();
+    }
+}
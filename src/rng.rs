@@ -0,0 +1,46 @@
+//! Random number generation.
+//!
+//! Randomized behaviors associated with Plumtree nodes draw random numbers from [`RngSource`],
+//! so that they can be reproduced by injecting a generator with a fixed seed.
+//!
+//! [`RngSource`]: ./trait.RngSource.html
+
+/// A source of random numbers.
+pub trait RngSource {
+    /// Returns the next random number.
+    fn next_u64(&mut self) -> u64;
+}
+
+/// A deterministic pseudo random number generator based on the [SplitMix64] algorithm.
+///
+/// This is the default [`RngSource`] of Plumtree nodes.
+///
+/// # Examples
+///
+/// ```
+/// use plumtree::rng::{RngSource, SplitMix64};
+///
+/// let mut a = SplitMix64::new(10);
+/// let mut b = SplitMix64::new(10);
+/// assert_eq!(a.next_u64(), b.next_u64());
+/// ```
+///
+/// [SplitMix64]: https://prng.di.unimi.it/splitmix64.c
+/// [`RngSource`]: ./trait.RngSource.html
+#[derive(Debug, Default, Clone)]
+pub struct SplitMix64(u64);
+impl SplitMix64 {
+    /// Makes a new `SplitMix64` instance with the given seed.
+    pub fn new(seed: u64) -> Self {
+        SplitMix64(seed)
+    }
+}
+impl RngSource for SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
@@ -0,0 +1,130 @@
+use crate::time::Clock;
+use crate::{NodeOptions, System};
+use std::fmt;
+
+type Owners<T> = Vec<(<T as System>::NodeId, u16)>;
+
+/// A snapshot of the reconstructable state of a Plumtree [Node].
+///
+/// A snapshot is taken by [`Node::export_state`] and a node is restored from it by [`Node::import_state`].
+/// If the `serde` feature is enabled, this type implements `Serialize` and `Deserialize`
+/// for persisting the state across restarts.
+///
+/// Note that the transient state (e.g., the queued actions and the outstanding `GRAFT` messages)
+/// is not included in the snapshot.
+/// The timeouts of the waiting `IHAVE` messages restart when the snapshot is imported.
+///
+/// [Node]: ./struct.Node.html
+/// [`Node::export_state`]: ./struct.Node.html#method.export_state
+/// [`Node::import_state`]: ./struct.Node.html#method.import_state
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T::NodeId: serde::Serialize, T::MessageId: serde::Serialize, \
+                     T::MessagePayload: serde::Serialize",
+        deserialize = "T::NodeId: serde::Deserialize<'de>, T::MessageId: serde::Deserialize<'de>, \
+                       T::MessagePayload: serde::Deserialize<'de>"
+    ))
+)]
+pub struct NodeState<T: System> {
+    version: u32,
+
+    /// The identifier of the node.
+    pub id: T::NodeId,
+
+    /// The options of the node.
+    pub options: NodeOptions,
+
+    /// The clock of the node.
+    pub clock: Clock,
+
+    /// The eager push peers of the node.
+    pub eager_push_peers: Vec<T::NodeId>,
+
+    /// The lazy push peers of the node.
+    pub lazy_push_peers: Vec<T::NodeId>,
+
+    /// The messages kept by the node.
+    pub messages: Vec<(T::MessageId, T::MessagePayload)>,
+
+    /// The identifiers of the messages broadcast by the node.
+    pub originated: Vec<T::MessageId>,
+
+    /// The rounds in which the node received the kept messages.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rounds: Vec<(T::MessageId, u16)>,
+
+    /// The messages waited by the node and their owners with the rounds advertised by them.
+    pub missings: Vec<(T::MessageId, Owners<T>)>,
+}
+impl<T: System> NodeState<T> {
+    /// The version of the snapshot format produced by this crate.
+    pub const VERSION: u32 = 1;
+
+    pub(crate) fn new(id: T::NodeId, options: NodeOptions, clock: Clock) -> Self {
+        NodeState {
+            version: Self::VERSION,
+            id,
+            options,
+            clock,
+            eager_push_peers: Vec::new(),
+            lazy_push_peers: Vec::new(),
+            messages: Vec::new(),
+            originated: Vec::new(),
+            rounds: Vec::new(),
+            missings: Vec::new(),
+        }
+    }
+
+    /// Returns the version of the format of the snapshot.
+    ///
+    /// [`Node::import_state`] rejects snapshots whose version differs from [`NodeState::VERSION`].
+    ///
+    /// [`Node::import_state`]: ./struct.Node.html#method.import_state
+    /// [`NodeState::VERSION`]: ./struct.NodeState.html#associatedconstant.VERSION
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+}
+impl<T: System> Clone for NodeState<T> {
+    fn clone(&self) -> Self {
+        NodeState {
+            version: self.version,
+            id: self.id.clone(),
+            options: self.options.clone(),
+            clock: self.clock.clone(),
+            eager_push_peers: self.eager_push_peers.clone(),
+            lazy_push_peers: self.lazy_push_peers.clone(),
+            messages: self.messages.clone(),
+            originated: self.originated.clone(),
+            rounds: self.rounds.clone(),
+            missings: self.missings.clone(),
+        }
+    }
+}
+impl<T: System> fmt::Debug for NodeState<T>
+where
+    T::NodeId: fmt::Debug,
+    T::MessageId: fmt::Debug,
+    T::MessagePayload: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "NodeState {{ version: {:?}, id: {:?}, options: {:?}, clock: {:?}, \
+             eager_push_peers: {:?}, lazy_push_peers: {:?}, messages: {:?}, originated: {:?}, \
+             rounds: {:?}, missings: {:?} }}",
+            self.version,
+            self.id,
+            self.options,
+            self.clock,
+            self.eager_push_peers,
+            self.lazy_push_peers,
+            self.messages,
+            self.originated,
+            self.rounds,
+            self.missings
+        )
+    }
+}
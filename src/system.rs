@@ -1,4 +1,5 @@
 use std::hash::Hash;
+use std::marker::PhantomData;
 
 /// This trait allows for defining a system to which Plumtree nodes belong.
 pub trait System {
@@ -9,5 +10,37 @@ pub trait System {
     type MessageId: Clone + Hash + Eq;
 
     /// Message payload.
+    ///
+    /// Payloads are cloned each time messages are delivered or forwarded.
+    /// If cloning is expensive or the payload type does not implement `Clone`,
+    /// consider wrapping it with [`ArcPayload`].
+    ///
+    /// [`ArcPayload`]: ./message/struct.ArcPayload.html
     type MessagePayload: Clone;
 }
+
+/// A generic [System] whose associated types are given as type parameters.
+///
+/// This saves defining a dedicated type for simple systems.
+///
+/// # Examples
+///
+/// ```
+/// use plumtree::{BasicSystem, Node};
+///
+/// let node = Node::<BasicSystem<String, u64, Vec<u8>>>::new("foo".to_owned());
+/// assert_eq!(node.id(), "foo");
+/// ```
+///
+/// [System]: ./trait.System.html
+pub struct BasicSystem<N, M, P>(PhantomData<(N, M, P)>);
+impl<N, M, P> System for BasicSystem<N, M, P>
+where
+    N: Clone + Hash + Eq,
+    M: Clone + Hash + Eq,
+    P: Clone,
+{
+    type NodeId = N;
+    type MessageId = M;
+    type MessagePayload = P;
+}
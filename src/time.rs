@@ -1,6 +1,8 @@
 //! Node local clock and time.
 use std;
+use std::cell::RefCell;
 use std::ops::{Add, AddAssign};
+use std::rc::Rc;
 use std::time::Duration;
 
 /// Node local clock.
@@ -12,6 +14,7 @@ use std::time::Duration;
 /// [`Node`]: ../struct.Node.html
 /// [`Clock::tick`]: ./struct.Clock.html#method.tick
 #[derive(Debug, Default, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Clock(Duration);
 impl Clock {
     /// Makes a new `Clock` instance.
@@ -51,22 +54,203 @@ impl Clock {
         self.0 += duration;
     }
 
+    /// Proceeds the time of the clock to the given absolute time.
+    ///
+    /// If the clock has already passed `target`, this method does nothing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use plumtree::time::Clock;
+    /// use std::time::Duration;
+    ///
+    /// let mut clock = Clock::new();
+    /// let mut other = Clock::new();
+    /// other.tick(Duration::from_secs(100));
+    ///
+    /// clock.tick_to(other.now());
+    /// assert_eq!(clock.now().as_duration(), Duration::from_secs(100));
+    ///
+    /// // The clock never goes backward
+    /// clock.tick(Duration::from_secs(10));
+    /// clock.tick_to(other.now());
+    /// assert_eq!(clock.now().as_duration(), Duration::from_secs(110));
+    /// ```
+    pub fn tick_to(&mut self, target: NodeTime) {
+        if self.0 < target.0 {
+            self.0 = target.0;
+        }
+    }
+
+    /// Returns the duration elapsed since the given time.
+    ///
+    /// If `earlier` is later than the current time, this method will return zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use plumtree::time::Clock;
+    /// use std::time::Duration;
+    ///
+    /// let mut clock = Clock::new();
+    /// let start = clock.now();
+    /// clock.tick(Duration::from_secs(100));
+    /// assert_eq!(clock.elapsed_since(start), Duration::from_secs(100));
+    ///
+    /// let end = clock.now();
+    /// assert_eq!(Clock::new().elapsed_since(end), Duration::from_secs(0));
+    /// ```
+    pub fn elapsed_since(&self, earlier: NodeTime) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+
     pub(crate) fn max() -> Self {
         let max = Duration::MAX;
         Clock(max)
     }
 }
 
+/// A clock shared by multiple nodes.
+///
+/// This is mainly intended for simulations.
+/// A `SharedClock` acts as the authoritative (global) clock, and the clock of each node is
+/// synchronized with it by [`SharedClock::sync`] at an arbitrary rate for modeling clock skew.
+///
+/// [`SharedClock::sync`]: ./struct.SharedClock.html#method.sync
+///
+/// # Examples
+///
+/// ```
+/// use plumtree::time::{Clock, SharedClock};
+/// use std::time::Duration;
+///
+/// let shared = SharedClock::new();
+/// let mut fast = Clock::new();
+/// let mut slow = Clock::new();
+///
+/// shared.clone().tick(Duration::from_secs(10));
+/// shared.sync(&mut fast, 1.0);
+/// shared.sync(&mut slow, 0.5);
+/// assert_eq!(fast.now().as_duration(), Duration::from_secs(10));
+/// assert_eq!(slow.now().as_duration(), Duration::from_secs(5));
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct SharedClock(Rc<RefCell<Clock>>);
+impl SharedClock {
+    /// Makes a new `SharedClock` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the current time of the clock.
+    pub fn now(&self) -> NodeTime {
+        self.0.borrow().now()
+    }
+
+    /// Proceeds the time of the clock by the given duration.
+    ///
+    /// The change is visible from all the clones of this instance.
+    pub fn tick(&self, duration: Duration) {
+        self.0.borrow_mut().tick(duration);
+    }
+
+    /// Proceeds the time of the given clock to the time of this clock multiplied by `rate`.
+    ///
+    /// A `rate` less (greater) than `1.0` simulates a clock running slower (faster)
+    /// than the shared one.
+    /// As with [`Clock::tick_to`], the given clock never goes backward.
+    ///
+    /// If the multiplied time overflows, the given clock proceeds to the maximum time.
+    /// If `rate` is negative or NaN, the given clock is left unchanged.
+    ///
+    /// [`Clock::tick_to`]: ./struct.Clock.html#method.tick_to
+    pub fn sync(&self, clock: &mut Clock, rate: f64) {
+        let secs = self.now().as_duration().as_secs_f64() * rate;
+        let target = match Duration::try_from_secs_f64(secs) {
+            Ok(target) => target,
+            Err(_) if secs > 0.0 => Duration::MAX,
+            Err(_) => return,
+        };
+        clock.tick_to(NodeTime(target));
+    }
+}
+
+/// A source of the node local time.
+///
+/// If a time source is set to a [`Node`] by [`Node::set_time_source`],
+/// the clock of the node follows the time source automatically,
+/// so it is not necessary to call [`Clock::tick`] manually.
+///
+/// [`Node`]: ../struct.Node.html
+/// [`Node::set_time_source`]: ../struct.Node.html#method.set_time_source
+/// [`Clock::tick`]: ./struct.Clock.html#method.tick
+///
+/// # Examples
+///
+/// ```
+/// use plumtree::time::{NodeTime, TimeSource};
+/// use std::time::Instant;
+///
+/// struct WallClock(Instant);
+/// impl TimeSource for WallClock {
+///     fn now(&self) -> NodeTime {
+///         NodeTime::from_duration(self.0.elapsed())
+///     }
+/// }
+/// ```
+pub trait TimeSource {
+    /// Returns the current time.
+    ///
+    /// The returned time should be monotonically non-decreasing.
+    /// If it goes backward, the clock of the node stays at the latest time.
+    fn now(&self) -> NodeTime;
+}
+
 /// Node local time.
 ///
 /// This represents the elapsed logical time since a clock was created.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NodeTime(Duration);
 impl NodeTime {
+    /// Makes a new `NodeTime` instance that represents the given elapsed time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use plumtree::time::NodeTime;
+    /// use std::time::Duration;
+    ///
+    /// let time = NodeTime::from_duration(Duration::from_secs(3));
+    /// assert_eq!(time.as_duration(), Duration::from_secs(3));
+    /// ```
+    pub fn from_duration(duration: Duration) -> Self {
+        NodeTime(duration)
+    }
+
     /// Converts `NodeTime` to `Duration`.
     pub fn as_duration(&self) -> Duration {
         self.0
     }
+
+    /// Returns `self + duration`, or `None` if the result overflows.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use plumtree::time::NodeTime;
+    /// use std::time::Duration;
+    ///
+    /// let time = NodeTime::from_duration(Duration::from_secs(3));
+    /// assert_eq!(
+    ///     time.checked_add(Duration::from_secs(2)),
+    ///     Some(NodeTime::from_duration(Duration::from_secs(5)))
+    /// );
+    /// assert_eq!(time.checked_add(Duration::MAX), None);
+    /// ```
+    pub fn checked_add(&self, duration: Duration) -> Option<Self> {
+        self.0.checked_add(duration).map(NodeTime)
+    }
 }
 impl Add<Duration> for NodeTime {
     type Output = Self;
@@ -0,0 +1,35 @@
+use crate::System;
+
+/// This trait allows for transforming message payloads kept by Plumtree [Node].
+///
+/// A node stores the payload returned by [`store`] and restores the original payload by [`load`]
+/// when it needs to send the message to other nodes.
+/// Delivered messages always have the original payloads.
+///
+/// This can be used, for example, for compressing kept payloads transparently.
+///
+/// [Node]: ./struct.Node.html
+/// [`store`]: ./trait.PayloadTransform.html#tymethod.store
+/// [`load`]: ./trait.PayloadTransform.html#tymethod.load
+pub trait PayloadTransform<T: System> {
+    /// Converts the given payload to the form to be kept by the node.
+    fn store(&self, payload: T::MessagePayload) -> T::MessagePayload;
+
+    /// Restores the original payload from the kept form.
+    fn load(&self, stored: &T::MessagePayload) -> T::MessagePayload;
+}
+
+/// The default [PayloadTransform] which keeps payloads as they are.
+///
+/// [PayloadTransform]: ./trait.PayloadTransform.html
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IdentityTransform;
+impl<T: System> PayloadTransform<T> for IdentityTransform {
+    fn store(&self, payload: T::MessagePayload) -> T::MessagePayload {
+        payload
+    }
+
+    fn load(&self, stored: &T::MessagePayload) -> T::MessagePayload {
+        stored.clone()
+    }
+}
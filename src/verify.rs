@@ -0,0 +1,13 @@
+use crate::message::ProtocolMessage;
+use crate::System;
+
+/// This trait allows for verifying incoming messages before Plumtree [Node] handles them.
+///
+/// For example, an implementation may check the signature of a message
+/// to make sure that it has really been sent by the claimed sender.
+///
+/// [Node]: ./struct.Node.html
+pub trait Verifier<T: System> {
+    /// Returns `true` if the given message is authentic.
+    fn verify(&self, message: &ProtocolMessage<T>) -> bool;
+}